@@ -0,0 +1,5 @@
+CREATE INDEX IF NOT EXISTS user_upload_user_id_idx
+            ON user_upload (user_id);
+
+CREATE INDEX IF NOT EXISTS gallery_created_at_id_idx
+            ON gallery (created_at DESC, id DESC);
@@ -0,0 +1,6 @@
+-- Used by the ownership filter of the nearest neighbours queries.
+CREATE INDEX IF NOT EXISTS gallery_embeddings_id_idx
+            ON gallery (embeddings_id);
+
+CREATE INDEX IF NOT EXISTS user_upload_gallery_id_idx
+            ON user_upload (gallery_id);
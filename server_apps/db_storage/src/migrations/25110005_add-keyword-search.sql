@@ -0,0 +1,24 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- array_to_string is only STABLE, generated columns and indexes need IMMUTABLE.
+CREATE OR REPLACE FUNCTION gallery_keywords_text(keywords text[])
+            RETURNS text
+            LANGUAGE sql IMMUTABLE PARALLEL SAFE
+            AS $$ SELECT coalesce(array_to_string(keywords, ' '), '') $$;
+
+ALTER TABLE gallery_rag_embeddings
+            ADD COLUMN IF NOT EXISTS search_document tsvector
+            GENERATED ALWAYS AS (
+                setweight(to_tsvector('english', gallery_keywords_text(keywords)), 'A') ||
+                setweight(to_tsvector('english', coalesce(img_alt, '')), 'B') ||
+                setweight(to_tsvector('english', coalesce(description, '')), 'C')
+            ) STORED;
+
+CREATE INDEX IF NOT EXISTS gallery_rag_embeddings_search_idx
+            ON gallery_rag_embeddings
+            USING gin (search_document);
+
+-- Typo tolerant matching over the tags
+CREATE INDEX IF NOT EXISTS gallery_rag_embeddings_keywords_trgm_idx
+            ON gallery_rag_embeddings
+            USING gin (gallery_keywords_text(keywords) gin_trgm_ops);
@@ -0,0 +1,11 @@
+-- Bucket objects of deleted photos. Rows are removed once the object is gone,
+-- failed deletes stay here to be retried.
+CREATE TABLE IF NOT EXISTS pending_object_delete(
+            id bigserial primary key not null,
+            bucket text not null,
+            path text not null,
+            attempts int not null default 0,
+            last_error text,
+            created_at timestamptz not null default now(),
+            updated_at timestamptz not null default now()
+);
@@ -0,0 +1,3 @@
+ALTER TABLE gallery
+            ADD COLUMN IF NOT EXISTS original_width int,
+            ADD COLUMN IF NOT EXISTS original_height int;
@@ -0,0 +1,30 @@
+-- User edits of the AI descriptors. NULL means the AI value is used.
+-- The feeder only writes the machine columns, so edits survive reprocessing.
+ALTER TABLE gallery_rag_embeddings
+            ADD COLUMN IF NOT EXISTS user_keywords text[],
+            ADD COLUMN IF NOT EXISTS user_description text,
+            ADD COLUMN IF NOT EXISTS user_theme text,
+            ADD COLUMN IF NOT EXISTS user_img_alt text,
+            ADD COLUMN IF NOT EXISTS user_img_aria text,
+            ADD COLUMN IF NOT EXISTS user_edited_at timestamptz;
+
+-- Keyword search runs over the values the user sees.
+DROP INDEX IF EXISTS gallery_rag_embeddings_search_idx;
+DROP INDEX IF EXISTS gallery_rag_embeddings_keywords_trgm_idx;
+ALTER TABLE gallery_rag_embeddings DROP COLUMN IF EXISTS search_document;
+
+ALTER TABLE gallery_rag_embeddings
+            ADD COLUMN search_document tsvector
+            GENERATED ALWAYS AS (
+                setweight(to_tsvector('english', gallery_keywords_text(coalesce(user_keywords, keywords))), 'A') ||
+                setweight(to_tsvector('english', coalesce(user_img_alt, img_alt, '')), 'B') ||
+                setweight(to_tsvector('english', coalesce(user_description, description, '')), 'C')
+            ) STORED;
+
+CREATE INDEX IF NOT EXISTS gallery_rag_embeddings_search_idx
+            ON gallery_rag_embeddings
+            USING gin (search_document);
+
+CREATE INDEX IF NOT EXISTS gallery_rag_embeddings_keywords_trgm_idx
+            ON gallery_rag_embeddings
+            USING gin (gallery_keywords_text(coalesce(user_keywords, keywords)) gin_trgm_ops);
@@ -0,0 +1,22 @@
+-- User curated collections. A photo may be in many albums.
+CREATE TABLE IF NOT EXISTS album(
+            id uuid primary key default gen_random_uuid(),
+            user_id text not null,
+            name text not null,
+            position int not null default 0,
+            -- Chosen by the user. The newest photo is used when unset.
+            cover_gallery_id uuid REFERENCES gallery(id) ON DELETE SET NULL,
+            created_at timestamptz not null default now(),
+            updated_at timestamptz not null default now()
+);
+
+CREATE INDEX IF NOT EXISTS album_user_id_idx ON album(user_id, position);
+
+CREATE TABLE IF NOT EXISTS album_item(
+            album_id uuid not null REFERENCES album(id) ON DELETE CASCADE,
+            gallery_id uuid not null REFERENCES gallery(id) ON DELETE CASCADE,
+            created_at timestamptz not null default now(),
+            primary key (album_id, gallery_id)
+);
+
+CREATE INDEX IF NOT EXISTS album_item_gallery_id_idx ON album_item(gallery_id);
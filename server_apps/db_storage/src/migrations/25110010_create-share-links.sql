@@ -0,0 +1,18 @@
+-- Public links to a photo or an album. Anyone holding the token can view it
+-- until it expires, runs out of views or is revoked.
+CREATE TABLE IF NOT EXISTS share_link(
+            id uuid primary key default gen_random_uuid(),
+            token text not null unique,
+            user_id text not null,
+            gallery_id uuid REFERENCES gallery(id) ON DELETE CASCADE,
+            album_id uuid REFERENCES album(id) ON DELETE CASCADE,
+            expires_at timestamptz not null,
+            -- Unlimited when null
+            max_views int,
+            views int not null default 0,
+            revoked_at timestamptz,
+            created_at timestamptz not null default now(),
+            CHECK (num_nonnulls(gallery_id, album_id) = 1)
+);
+
+CREATE INDEX IF NOT EXISTS share_link_user_id_idx ON share_link(user_id);
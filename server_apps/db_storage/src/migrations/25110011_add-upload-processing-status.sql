@@ -0,0 +1,16 @@
+-- Feeder progress of each upload. The feeder updates it at every step.
+ALTER TABLE user_upload
+            ADD COLUMN IF NOT EXISTS processing_status text not null default 'pending',
+            ADD COLUMN IF NOT EXISTS processing_error text,
+            ADD COLUMN IF NOT EXISTS processing_updated_at timestamptz not null default now();
+
+-- Uploads processed before the status existed.
+UPDATE user_upload u SET processing_status = case
+            when ge.description is not null then 'described'
+            when g.embeddings_id is not null then 'embedded'
+            else processing_status
+        end
+    from gallery g left join gallery_rag_embeddings ge on ge.id = g.embeddings_id
+    where g.id = u.gallery_id;
+
+CREATE INDEX IF NOT EXISTS user_upload_processing_idx ON user_upload(user_id, processing_updated_at);
@@ -0,0 +1,32 @@
+-- Name given by the user. `filename` is the storage key, `feeder/<upload id>`
+-- for new uploads.
+ALTER TABLE user_upload ADD COLUMN IF NOT EXISTS original_filename text;
+UPDATE user_upload SET original_filename = regexp_replace(filename, '^feeder/', '')
+    where original_filename is null;
+
+-- Repeated requests that never got processed. The processed or oldest one stays.
+DELETE from user_upload u using user_upload o
+    where u.user_id = o.user_id and u.filehash = o.filehash and u.id <> o.id
+        and u.gallery_id is null
+        and (o.gallery_id is not null or (o.created_at, o.id) < (u.created_at, u.id));
+
+-- Uploads processed more than once, from before the uploads were deduplicated.
+-- The oldest processed one stays, the others point to it and their gallery
+-- entries are archived. Nothing is deleted, the user can restore them.
+ALTER TABLE user_upload ADD COLUMN IF NOT EXISTS duplicate_of uuid;
+ALTER TABLE gallery ADD COLUMN IF NOT EXISTS archived_at timestamptz;
+UPDATE user_upload u SET duplicate_of = k.id
+    from (
+        SELECT distinct on (user_id, filehash) id, user_id, filehash
+        from user_upload where gallery_id is not null
+        order by user_id, filehash, created_at, id
+    ) k
+    where u.user_id = k.user_id and u.filehash = k.filehash and u.id <> k.id
+        and u.gallery_id is not null;
+UPDATE gallery g SET archived_at = now()
+    from user_upload u
+    where u.gallery_id = g.id and u.duplicate_of is not null and g.archived_at is null;
+
+-- A user uploads the same bytes once. Legacy duplicates are left out.
+CREATE UNIQUE INDEX IF NOT EXISTS user_upload_user_filehash_idx ON user_upload(user_id, filehash)
+    where duplicate_of is null;
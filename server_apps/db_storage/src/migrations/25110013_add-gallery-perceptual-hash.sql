@@ -0,0 +1,5 @@
+-- 64 bit difference hash of the image. Near-duplicates differ by a few bits.
+-- Archived photos are hidden from the gallery and the searches.
+ALTER TABLE gallery
+            ADD COLUMN IF NOT EXISTS perceptual_hash bigint,
+            ADD COLUMN IF NOT EXISTS archived_at timestamptz;
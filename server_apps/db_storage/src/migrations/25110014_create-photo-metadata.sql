@@ -0,0 +1,25 @@
+-- EXIF, IPTC and XMP metadata embedded in the uploaded file.
+CREATE TABLE IF NOT EXISTS photo_metadata(
+            gallery_id uuid primary key REFERENCES gallery(id) ON DELETE CASCADE,
+            -- Camera wall clock. The offset is in minutes east of UTC, null when unknown.
+            taken_at timestamp,
+            taken_at_offset smallint,
+            camera_make text,
+            camera_model text,
+            lens_model text,
+            -- Seconds
+            exposure_time double precision,
+            f_number double precision,
+            focal_length double precision,
+            iso int,
+            latitude double precision,
+            longitude double precision,
+            -- Meters over the sea level
+            altitude double precision,
+            -- EXIF orientation, 1 to 8
+            orientation smallint,
+            keywords text[],
+            caption text,
+            created_at timestamptz not null default now(),
+            updated_at timestamptz not null default now()
+);
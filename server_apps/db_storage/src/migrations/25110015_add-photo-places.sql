@@ -0,0 +1,10 @@
+-- Place closest to the GPS coordinates, from the offline GeoNames dataset.
+ALTER TABLE photo_metadata
+            ADD COLUMN IF NOT EXISTS city text,
+            ADD COLUMN IF NOT EXISTS region text,
+            ADD COLUMN IF NOT EXISTS country text;
+
+-- Map bounding boxes
+CREATE INDEX IF NOT EXISTS photo_metadata_coordinates_idx
+            ON photo_metadata(latitude, longitude)
+            WHERE latitude IS NOT NULL AND longitude IS NOT NULL;
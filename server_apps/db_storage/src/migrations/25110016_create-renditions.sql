@@ -0,0 +1,11 @@
+-- Resized copies of a photo, bounded on their longest edge. Served as a srcset.
+CREATE TABLE IF NOT EXISTS rendition(
+            gallery_id uuid not null REFERENCES gallery(id) ON DELETE CASCADE,
+            -- `webp` or `avif`
+            format text not null,
+            width int not null,
+            height int not null,
+            path text not null,
+            created_at timestamptz not null default now(),
+            primary key (gallery_id, format, width)
+);
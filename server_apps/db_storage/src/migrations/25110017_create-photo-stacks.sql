@@ -0,0 +1,16 @@
+-- RAW+JPEG pairs and bursts, shown as a single gallery entry.
+CREATE TABLE IF NOT EXISTS photo_stack(
+            id uuid primary key default gen_random_uuid(),
+            user_id text not null,
+            -- `raw_jpeg` or `burst`
+            kind text not null,
+            -- Chosen by the user. The first processed photo is used when unset.
+            cover_gallery_id uuid REFERENCES gallery(id) ON DELETE SET NULL,
+            created_at timestamptz not null default now(),
+            updated_at timestamptz not null default now()
+);
+
+ALTER TABLE gallery ADD COLUMN IF NOT EXISTS stack_id uuid REFERENCES photo_stack(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS gallery_stack_id_idx ON gallery(stack_id) where stack_id is not null;
+CREATE INDEX IF NOT EXISTS photo_metadata_taken_at_idx ON photo_metadata(taken_at) where taken_at is not null;
@@ -0,0 +1,13 @@
+-- CLIP embeddings of sampled frames of animated images. The poster frame is
+-- also the gallery embedding.
+CREATE TABLE IF NOT EXISTS gallery_frame_embeddings(
+            gallery_id uuid not null REFERENCES gallery(id) ON DELETE CASCADE,
+            frame_index int not null,
+            embedding vector(512) not null,
+            created_at timestamptz not null default now(),
+            primary key (gallery_id, frame_index)
+);
+
+CREATE INDEX IF NOT EXISTS gallery_frame_embeddings_idx
+            ON gallery_frame_embeddings
+            USING diskann (embedding);
@@ -0,0 +1,2 @@
+-- BlurHash of the thumbnail, drawn by the clients while the thumbnail loads.
+ALTER TABLE gallery ADD COLUMN IF NOT EXISTS blurhash text;
@@ -0,0 +1,17 @@
+-- Dominant colours of the thumbnail, most dominant first. Kept in CIELAB so
+-- colour distances match what people see.
+CREATE TABLE IF NOT EXISTS embeddings_palette(
+            embeddings_id bigint not null REFERENCES gallery_rag_embeddings(id) ON DELETE CASCADE,
+            position int not null,
+            lab_l real not null,
+            lab_a real not null,
+            lab_b real not null,
+            hex text not null,
+            color_name text not null,
+            -- Fraction of the pixels in the cluster, from 0 to 1.
+            share real not null,
+            primary key (embeddings_id, position)
+);
+
+CREATE INDEX IF NOT EXISTS embeddings_palette_color_name_idx
+            ON embeddings_palette (color_name, embeddings_id);
@@ -0,0 +1,240 @@
+use derive_getters::Getters;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::errors::QueryResult;
+
+/// User curated collection of gallery items.
+#[derive(Debug, Getters, sqlx::FromRow)]
+pub struct Album {
+    id: Uuid,
+    name: String,
+    /// Display order in the user album list, lower first.
+    position: i32,
+    photo_count: i64,
+    /// Gallery item shown as the album cover.
+    cover_id: Option<Uuid>,
+    /// Thumbnail bucket path of the cover.
+    cover_path: Option<String>,
+    created_at: OffsetDateTime,
+    updated_at: OffsetDateTime,
+}
+
+impl Album {
+    /// New albums go to the end of the list.
+    pub async fn create(conn: &crate::DbConn, user_id: &str, name: &str) -> QueryResult<Self> {
+        Ok(sqlx::query_as::<_, Album>(
+            "
+            INSERT into album(user_id, name, position)
+            SELECT $1, $2, coalesce(max(position) + 1, 0) from album where user_id=$1
+            returning id, name, position, 0::int8 as photo_count, null::uuid as cover_id,
+                null::text as cover_path, created_at, updated_at
+            ",
+        )
+        .bind(user_id)
+        .bind(name)
+        .fetch_one(conn)
+        .await?)
+    }
+
+    /// Albums of the user in display order.
+    /// The cover is the one chosen by the user, otherwise the newest processed photo.
+    pub async fn list_for_user(conn: &crate::DbConn, user_id: &str) -> QueryResult<Vec<Self>> {
+        Ok(sqlx::query_as::<_, Album>(
+            "
+            SELECT a.id, a.name, a.position,
+                (SELECT count(1) from album_item ai where ai.album_id = a.id) as photo_count,
+                cover.id as cover_id, cover.thumbnail_path as cover_path,
+                a.created_at, a.updated_at
+            from album a
+                left join lateral (
+                    SELECT g.id, g.thumbnail_path
+                    from album_item ai join gallery g on g.id = ai.gallery_id
+                    where ai.album_id = a.id and g.thumbnail_path is not null
+                    order by (g.id = a.cover_gallery_id) is true desc, ai.created_at desc, g.id desc
+                    limit 1
+                ) cover on true
+            where a.user_id=$1
+            order by a.position, a.created_at
+            ",
+        )
+        .bind(user_id)
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// False when the album is not found.
+    pub async fn rename(
+        conn: &crate::DbConn,
+        user_id: &str,
+        album_id: &Uuid,
+        name: &str,
+    ) -> QueryResult<bool> {
+        let updated =
+            sqlx::query("UPDATE album SET name=$3, updated_at=now() where id=$1 and user_id=$2")
+                .bind(album_id)
+                .bind(user_id)
+                .bind(name)
+                .execute(conn)
+                .await?;
+        Ok(updated.rows_affected() > 0)
+    }
+
+    /// The photo must be in the album. False when any of them is not found.
+    pub async fn set_cover(
+        conn: &crate::DbConn,
+        user_id: &str,
+        album_id: &Uuid,
+        gallery_id: &Uuid,
+    ) -> QueryResult<bool> {
+        let updated = sqlx::query(
+            "
+            UPDATE album a SET cover_gallery_id=$3, updated_at=now()
+            where a.id=$1 and a.user_id=$2
+                and exists (SELECT 1 from album_item ai where ai.album_id = a.id and ai.gallery_id = $3)
+            ",
+        )
+        .bind(album_id)
+        .bind(user_id)
+        .bind(gallery_id)
+        .execute(conn)
+        .await?;
+        Ok(updated.rows_affected() > 0)
+    }
+
+    /// Only the album is removed, its photos stay in the gallery.
+    /// False when the album is not found.
+    pub async fn delete(conn: &crate::DbConn, user_id: &str, album_id: &Uuid) -> QueryResult<bool> {
+        let deleted = sqlx::query("DELETE from album where id=$1 and user_id=$2")
+            .bind(album_id)
+            .bind(user_id)
+            .execute(conn)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    /// Positions the albums in the given order. The albums left out go after
+    /// them, in their current order.
+    /// Nothing changes when an id is repeated or not found.
+    pub async fn reorder(
+        conn: &crate::DbConn,
+        user_id: &str,
+        album_ids: &[Uuid],
+    ) -> QueryResult<bool> {
+        let mut tx = conn.begin().await?;
+        let updated = sqlx::query(
+            "
+            UPDATE album a SET position=o.position - 1, updated_at=now()
+            from unnest($2::uuid[]) with ordinality o(id, position)
+            where a.id = o.id and a.user_id=$1
+            ",
+        )
+        .bind(user_id)
+        .bind(album_ids)
+        .execute(&mut *tx)
+        .await?;
+
+        // Rollback on drop
+        if updated.rows_affected() != album_ids.len() as u64 {
+            return Ok(false);
+        }
+        sqlx::query(
+            "
+            UPDATE album a SET position=$3 + r.ordinal - 1, updated_at=now()
+            from (
+                SELECT id, row_number() over (order by position, created_at, id) as ordinal
+                from album where user_id=$1 and id <> all($2)
+            ) r
+            where a.id = r.id
+            ",
+        )
+        .bind(user_id)
+        .bind(album_ids)
+        .bind(album_ids.len() as i32)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Adds the user photos to the album. Photos already in it are skipped.
+    /// None when the album is not found, otherwise the number of photos added.
+    pub async fn add_photos(
+        conn: &crate::DbConn,
+        user_id: &str,
+        album_id: &Uuid,
+        gallery_ids: &[Uuid],
+    ) -> QueryResult<Option<u64>> {
+        if !Self::is_owned(conn, user_id, album_id).await? {
+            return Ok(None);
+        }
+
+        let added = sqlx::query(
+            "
+            INSERT into album_item(album_id, gallery_id)
+            SELECT $1, u.gallery_id from user_upload u
+            where u.user_id=$2 and u.gallery_id = any($3)
+            on conflict do nothing
+            ",
+        )
+        .bind(album_id)
+        .bind(user_id)
+        .bind(gallery_ids)
+        .execute(conn)
+        .await?;
+
+        Ok(Some(added.rows_affected()))
+    }
+
+    /// Takes the photos out of the album, they stay in the gallery.
+    /// A removed cover falls back to the newest photo.
+    /// None when the album is not found, otherwise the number of photos removed.
+    pub async fn remove_photos(
+        conn: &crate::DbConn,
+        user_id: &str,
+        album_id: &Uuid,
+        gallery_ids: &[Uuid],
+    ) -> QueryResult<Option<u64>> {
+        let mut tx = conn.begin().await?;
+        let album = sqlx::query(
+            "
+            UPDATE album SET updated_at=now(),
+                cover_gallery_id = case when cover_gallery_id = any($3) then null else cover_gallery_id end
+            where id=$1 and user_id=$2
+            ",
+        )
+        .bind(album_id)
+        .bind(user_id)
+        .bind(gallery_ids)
+        .execute(&mut *tx)
+        .await?;
+        if album.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let removed =
+            sqlx::query("DELETE from album_item where album_id=$1 and gallery_id = any($2)")
+                .bind(album_id)
+                .bind(gallery_ids)
+                .execute(&mut *tx)
+                .await?;
+        tx.commit().await?;
+
+        Ok(Some(removed.rows_affected()))
+    }
+
+    async fn is_owned(conn: &crate::DbConn, user_id: &str, album_id: &Uuid) -> QueryResult<bool> {
+        let owned: Option<(Uuid,)> =
+            sqlx::query_as("SELECT id from album where id=$1 and user_id=$2")
+                .bind(album_id)
+                .bind(user_id)
+                .fetch_optional(conn)
+                .await?;
+        Ok(owned.is_some())
+    }
+
+    pub fn set_signed_url(&mut self, url: String) {
+        self.cover_path = Some(url);
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use derive_getters::Getters;
+use uuid::Uuid;
+
+use crate::errors::QueryResult;
+use crate::models::user_photos::UserPhoto;
+
+/// Differing bits of the perceptual hash up to which two photos are near-duplicates.
+pub const DEFAULT_MAX_DISTANCE: i32 = 8;
+
+/// Photo of a near-duplicate group.
+#[derive(Debug, Getters, sqlx::FromRow)]
+pub struct DuplicatePhoto {
+    #[sqlx(flatten)]
+    photo: UserPhoto,
+    original_width: Option<i32>,
+    original_height: Option<i32>,
+    filesize: i32,
+}
+
+impl DuplicatePhoto {
+    pub fn photo_mut(&mut self) -> &mut UserPhoto {
+        &mut self.photo
+    }
+
+    fn pixels(&self) -> i64 {
+        self.original_width.unwrap_or(0) as i64 * self.original_height.unwrap_or(0) as i64
+    }
+}
+
+/// Photos of the user whose perceptual hashes are close.
+/// The first photo is the suggested one to keep: the largest, then the oldest.
+#[derive(Debug, Getters)]
+pub struct DuplicateGroup {
+    photos: Vec<DuplicatePhoto>,
+}
+
+impl DuplicateGroup {
+    pub fn photos_mut(&mut self) -> &mut Vec<DuplicatePhoto> {
+        &mut self.photos
+    }
+
+    /// Near-duplicate groups of the user, biggest group first.
+    /// Two photos are grouped when their hashes differ in at most `max_distance` bits,
+    /// the grouping is transitive. Archived photos are left out.
+    /// The hashes are split into `max_distance + 1` bands, two hashes that
+    /// close share at least one of them. Only photos sharing a band are compared.
+    pub async fn find_for_user(
+        conn: &crate::DbConn,
+        user_id: &str,
+        max_distance: i32,
+    ) -> QueryResult<Vec<Self>> {
+        let pairs: Vec<(Uuid, Uuid)> = sqlx::query_as(
+            "
+            with hashed as (
+                SELECT g.id, g.perceptual_hash
+                from gallery g join user_upload u on u.gallery_id=g.id
+                where u.user_id=$1 and g.perceptual_hash is not null and g.archived_at is null
+            ),
+            bands as (
+                SELECT h.id, h.perceptual_hash, b.band,
+                    substring(h.perceptual_hash::bit(64)
+                        from b.band * 64 / ($2 + 1) + 1
+                        for (b.band + 1) * 64 / ($2 + 1) - b.band * 64 / ($2 + 1)) as bits
+                from hashed h, generate_series(0, $2) b(band)
+            )
+            SELECT distinct a.id, b.id
+            from bands a join bands b on a.band = b.band and a.bits = b.bits and a.id < b.id
+            where bit_count((a.perceptual_hash # b.perceptual_hash)::bit(64)) <= $2
+            ",
+        )
+        .bind(user_id)
+        .bind(max_distance)
+        .fetch_all(conn)
+        .await?;
+
+        let ids = group_pairs(&pairs);
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let all_ids: Vec<Uuid> = ids.iter().flatten().copied().collect();
+        let photos = sqlx::query_as::<_, DuplicatePhoto>(
+            "
+            SELECT g.id, g.created_at, g.thumbnail_path, g.thumbnail_ratio, g.thumbnail_width, g.thumbnail_height, g.blurhash,
+                coalesce(ge.user_img_aria, ge.img_aria) as img_aria, coalesce(ge.user_img_alt, ge.img_alt) as img_alt, coalesce(ge.user_theme, ge.theme) as theme,
+                g.original_width, g.original_height, u.filesize
+            from gallery g
+                join user_upload u on u.gallery_id=g.id
+                left join gallery_rag_embeddings ge on g.embeddings_id = ge.id
+            where u.user_id=$1 and g.id = any($2)
+            ",
+        )
+        .bind(user_id)
+        .bind(&all_ids)
+        .fetch_all(conn)
+        .await?;
+
+        let mut by_id: HashMap<Uuid, DuplicatePhoto> =
+            photos.into_iter().map(|p| (*p.photo.id(), p)).collect();
+        let mut groups: Vec<Self> = ids
+            .into_iter()
+            .map(|group| {
+                let mut photos: Vec<DuplicatePhoto> =
+                    group.iter().filter_map(|id| by_id.remove(id)).collect();
+                photos.sort_by(|a, b| {
+                    b.pixels()
+                        .cmp(&a.pixels())
+                        .then(b.filesize.cmp(&a.filesize))
+                        .then(a.photo.created_at().cmp(b.photo.created_at()))
+                });
+                Self { photos }
+            })
+            // Deleted between both queries
+            .filter(|g| g.photos.len() > 1)
+            .collect();
+        groups.sort_by(|a, b| b.photos.len().cmp(&a.photos.len()));
+
+        Ok(groups)
+    }
+
+    /// Hides the photos from the gallery and restores `keep` if it was archived.
+    /// None when `keep` is not found, otherwise the number of photos archived.
+    pub async fn archive(
+        conn: &crate::DbConn,
+        user_id: &str,
+        keep: &Uuid,
+        gallery_ids: &[Uuid],
+    ) -> QueryResult<Option<u64>> {
+        let mut tx = conn.begin().await?;
+        let kept = sqlx::query(
+            "
+            UPDATE gallery g SET archived_at=null, updated_at=now()
+            from user_upload u
+            where u.gallery_id=g.id and u.user_id=$1 and g.id=$2
+            ",
+        )
+        .bind(user_id)
+        .bind(keep)
+        .execute(&mut *tx)
+        .await?;
+        if kept.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let archived = sqlx::query(
+            "
+            UPDATE gallery g SET archived_at=now(), updated_at=now()
+            from user_upload u
+            where u.gallery_id=g.id and u.user_id=$1 and g.id = any($2) and g.id <> $3
+                and g.archived_at is null
+            ",
+        )
+        .bind(user_id)
+        .bind(gallery_ids)
+        .bind(keep)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(archived.rows_affected()))
+    }
+}
+
+/// Connected components of the pairs, each one sorted. Union-find.
+fn group_pairs(pairs: &[(Uuid, Uuid)]) -> Vec<Vec<Uuid>> {
+    fn root(parents: &mut HashMap<Uuid, Uuid>, id: Uuid) -> Uuid {
+        let parent = *parents.entry(id).or_insert(id);
+        if parent == id {
+            return id;
+        }
+        let found = root(parents, parent);
+        parents.insert(id, found);
+        found
+    }
+
+    let mut parents: HashMap<Uuid, Uuid> = HashMap::new();
+    for (a, b) in pairs {
+        let (root_a, root_b) = (root(&mut parents, *a), root(&mut parents, *b));
+        if root_a != root_b {
+            parents.insert(root_a, root_b);
+        }
+    }
+
+    let ids: Vec<Uuid> = parents.keys().copied().collect();
+    let mut groups: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for id in ids {
+        let group_root = root(&mut parents, id);
+        groups.entry(group_root).or_default().push(id);
+    }
+
+    let mut groups: Vec<Vec<Uuid>> = groups.into_values().collect();
+    for group in groups.iter_mut() {
+        group.sort();
+    }
+    groups.sort();
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_groups_transitive_pairs() {
+        let ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        let pairs = vec![(ids[0], ids[1]), (ids[2], ids[3]), (ids[1], ids[4])];
+
+        let groups = group_pairs(&pairs);
+
+        let mut first = vec![ids[0], ids[1], ids[4]];
+        first.sort();
+        let mut second = vec![ids[2], ids[3]];
+        second.sort();
+        assert_eq!(groups.len(), 2);
+        assert!(groups.contains(&first));
+        assert!(groups.contains(&second));
+    }
+}
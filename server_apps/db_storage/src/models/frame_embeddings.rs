@@ -0,0 +1,39 @@
+use pgvector::Vector;
+use uuid::Uuid;
+
+use crate::errors::QueryResult;
+
+/// CLIP embedding of a frame of an animated image.
+#[derive(Debug, Clone)]
+pub struct FrameEmbedding {
+    pub frame_index: i32,
+    pub embedding: Vec<f32>,
+}
+
+impl FrameEmbedding {
+    /// Replaces the frame embeddings of the gallery item.
+    pub async fn save_for_gallery(
+        conn: &crate::DbConn,
+        gallery_id: &Uuid,
+        frames: &[FrameEmbedding],
+    ) -> QueryResult<()> {
+        let mut tx = conn.begin().await?;
+        sqlx::query("DELETE from gallery_frame_embeddings where gallery_id=$1")
+            .bind(gallery_id)
+            .execute(&mut *tx)
+            .await?;
+        for frame in frames {
+            sqlx::query(
+                "INSERT into gallery_frame_embeddings(gallery_id, frame_index, embedding) values ($1, $2, $3)",
+            )
+            .bind(gallery_id)
+            .bind(frame.frame_index)
+            .bind(Vector::from(frame.embedding.clone()))
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use derive_getters::Getters;
+use uuid::Uuid;
+
+use crate::errors::QueryResult;
+
+/// Area of the map in decimal degrees. `min_longitude` greater than
+/// `max_longitude` crosses the antimeridian.
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min_latitude: f64,
+    pub min_longitude: f64,
+    pub max_latitude: f64,
+    pub max_longitude: f64,
+}
+
+/// Geotagged photos of a grid cell.
+#[derive(Debug, Getters, sqlx::FromRow)]
+pub struct MapCluster {
+    /// Mean position of the photos
+    latitude: f64,
+    longitude: f64,
+    count: i64,
+    /// Newest photo of the cluster
+    photo_id: Uuid,
+    thumbnail_path: Option<String>,
+}
+
+impl MapCluster {
+    /// User photos inside the box grouped in square cells of `cell_degrees`,
+    /// the most populated first.
+    pub async fn list_for_user(
+        conn: &crate::DbConn,
+        user_id: &str,
+        bbox: &BoundingBox,
+        cell_degrees: f64,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        Ok(sqlx::query_as::<_, MapCluster>(
+            "
+            SELECT avg(pm.latitude) as latitude, avg(pm.longitude) as longitude, count(1) as count,
+                (array_agg(g.id order by g.created_at desc, g.id desc))[1] as photo_id,
+                (array_agg(g.thumbnail_path order by g.created_at desc, g.id desc))[1] as thumbnail_path
+            from photo_metadata pm
+                join gallery g on g.id = pm.gallery_id
+                join user_upload u on u.gallery_id=g.id
+                join gallery_rag_embeddings ge on g.embeddings_id = ge.id
+            where u.user_id=$1 and g.archived_at is null
+                and pm.latitude between $2 and $4
+                and case when $3 <= $5 then pm.longitude between $3 and $5
+                    else pm.longitude >= $3 or pm.longitude <= $5 end
+            group by floor(pm.latitude / $6), floor(pm.longitude / $6)
+            order by count desc
+            limit $7
+            ",
+        )
+        .bind(user_id)
+        .bind(bbox.min_latitude)
+        .bind(bbox.min_longitude)
+        .bind(bbox.max_latitude)
+        .bind(bbox.max_longitude)
+        .bind(cell_degrees)
+        .bind(limit)
+        .fetch_all(conn)
+        .await?)
+    }
+
+    pub fn set_signed_url(&mut self, url: String) {
+        self.thumbnail_path = Some(url);
+    }
+}
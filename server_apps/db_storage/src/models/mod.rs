@@ -2,11 +2,24 @@ use derive_getters::Getters;
 use futures_util::TryStreamExt;
 use pgvector::Vector;
 use sqlx::Row;
+use sqlx::postgres::PgRow;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
 use crate::DbConn;
 use crate::errors::{QueryError, QueryResult};
+pub mod albums;
+pub mod duplicates;
+pub mod frame_embeddings;
+pub mod map;
+pub mod palette;
+pub mod photo_metadata;
+pub mod renditions;
+pub mod share_links;
+pub mod stacks;
+pub mod storage_cleanup;
+pub mod timeline;
+pub mod upload_progress;
 pub mod user_photos;
 
 pub struct NewThumbnail<'a> {
@@ -20,6 +33,64 @@ pub struct NewEmbeddings {
     pub embeddings_id: i64,
 }
 
+/// Upload requested as part of a batch.
+#[derive(Debug, Clone, Copy)]
+pub struct NewUpload<'a> {
+    /// Name given by the user. It is not used in the storage key.
+    pub name: &'a str,
+    pub filesize: i32,
+    /// SHA-256 of the file, hex encoded.
+    pub filehash: &'a str,
+}
+
+/// Why an upload of a batch was not created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UploadRejection {
+    /// The user uploaded the same file before.
+    Duplicated,
+    /// The same file appears earlier in the batch.
+    RepeatedInBatch,
+}
+
+/// Descriptors edited by the user. `None` keeps the current value.
+#[derive(Debug, Clone, Default)]
+pub struct DescriptorEdits {
+    pub keywords: Option<Vec<String>>,
+    pub description: Option<String>,
+    pub theme: Option<String>,
+    pub img_alt: Option<String>,
+    pub img_aria: Option<String>,
+    /// Fields that go back to the AI generated value.
+    pub reset: Vec<DescriptorField>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DescriptorField {
+    Keywords,
+    Description,
+    Theme,
+    ImgAlt,
+    ImgAria,
+}
+
+/// Nearest neighbours search options.
+#[derive(Debug, Clone, Copy)]
+pub struct NearestQuery {
+    /// Max amount of results.
+    pub limit: i64,
+    /// Cosine distance cut, results further than this are dropped.
+    pub max_distance: Option<f64>,
+}
+
+impl Default for NearestQuery {
+    fn default() -> Self {
+        Self {
+            limit: 10,
+            max_distance: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Getters, sqlx::FromRow)]
 pub struct Gallery {
     id: Uuid,
@@ -117,6 +188,45 @@ impl Gallery {
         Ok(())
     }
 
+    /// Dimensions of the uploaded image, before any resize.
+    pub async fn set_original_dimensions(
+        &self,
+        conn: &crate::DbConn,
+        width: i32,
+        height: i32,
+    ) -> QueryResult<()> {
+        sqlx::query("UPDATE gallery SET original_width=$2, original_height=$3 where id=$1")
+            .bind(self.id)
+            .bind(width)
+            .bind(height)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// 64 bit difference hash of the image, see `duplicates`.
+    pub async fn set_perceptual_hash(&self, conn: &crate::DbConn, hash: i64) -> QueryResult<()> {
+        sqlx::query("UPDATE gallery SET perceptual_hash=$2 where id=$1")
+            .bind(self.id)
+            .bind(hash)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// BlurHash placeholder of the thumbnail.
+    pub async fn set_blurhash(&self, conn: &crate::DbConn, blurhash: &str) -> QueryResult<()> {
+        sqlx::query("UPDATE gallery SET blurhash=$2 where id=$1")
+            .bind(self.id)
+            .bind(blurhash)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
     /// Deletes
     /// Consumes itself to drop the value.
     pub async fn delete_one(self, conn: &crate::DbConn) -> QueryResult<()> {
@@ -225,39 +335,89 @@ impl GalleryEmbeddings {
         Ok(())
     }
 
+    /// Nearest embeddings owned by the user.
+    /// The ownership filter is an EXISTS over the same table the ORDER BY runs on,
+    /// so the planner may keep the diskann index scan. Diskann streams candidates
+    /// until LIMIT rows pass the filter, recall holds even for small libraries.
+    /// For a very selective user the planner prefers the user_id index and an
+    /// exact sort, which is correct as well.
+    /// The threshold is applied after the LIMIT to not break the index ordering.
     pub async fn find_nearest(
-        embedding: Vec<f32>,
         conn: &crate::DbConn,
+        user_id: &str,
+        embedding: Vec<f32>,
+        query: &NearestQuery,
     ) -> Result<Vec<GalleryEmbeddings>, QueryError> {
         let embed_vec = Vector::from(embedding);
         let mut embeddings_rows = sqlx::query(
             r#"
-              SELECT * 
-              FROM gallery_rag_embeddings
-              ORDER BY embedding <=> $1 LIMIT 10 
+              SELECT * FROM (
+                  SELECT ge.*, ge.embedding <=> $2 as distance
+                  FROM gallery_rag_embeddings ge
+                  WHERE EXISTS (
+                      SELECT 1 FROM gallery g join user_upload u on u.gallery_id=g.id
+                      WHERE g.embeddings_id = ge.id and u.user_id = $1
+                  )
+                  ORDER BY ge.embedding <=> $2 LIMIT $3
+              ) nearest
+              WHERE $4::float8 is null or nearest.distance <= $4
+              ORDER BY nearest.distance
           "#,
         )
+        .bind(user_id)
         .bind(embed_vec)
+        .bind(query.limit)
+        .bind(query.max_distance)
         .fetch(conn);
 
         let mut res = Vec::new();
         while let Ok(Some(row)) = embeddings_rows.try_next().await {
-            let row_vec: Vector = row.get("embedding");
-            res.push(GalleryEmbeddings {
-                id: row.get("id"),
-                path: row.get("path"),
-                keywords: row.get("keywords"),
-                description: row.get("description"),
-                embedding: row_vec.to_vec(),
-                theme: row.get("theme"),
-                img_aria: row.get("img_aria"),
-                img_alt: row.get("img_alt"),
-            });
+            res.push(Self::from_row(&row));
         }
 
         Ok(res)
     }
 
+    /// Embeddings linked to a gallery item. None if the item does not exist,
+    /// is not processed yet or belongs to someone else.
+    pub async fn get_for_gallery(
+        conn: &crate::DbConn,
+        user_id: &str,
+        gallery_id: &Uuid,
+    ) -> Result<Option<GalleryEmbeddings>, QueryError> {
+        let row = sqlx::query(
+            r#"
+              SELECT ge.*
+              FROM gallery g
+                  join user_upload u on u.gallery_id=g.id
+                  join gallery_rag_embeddings ge on g.embeddings_id = ge.id
+              WHERE g.id = $2 and u.user_id = $1
+          "#,
+        )
+        .bind(user_id)
+        .bind(gallery_id)
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(row.as_ref().map(Self::from_row))
+    }
+
+    /// pgvector columns are not supported by the query macros.
+    fn from_row(row: &PgRow) -> Self {
+        let row_vec: Vector = row.get("embedding");
+        GalleryEmbeddings {
+            id: row.get("id"),
+            path: row.get("path"),
+            keywords: row.get("keywords"),
+            description: row.get("description"),
+            embedding: row_vec.to_vec(),
+            theme: row.get("theme"),
+            img_aria: row.get("img_aria"),
+            img_alt: row.get("img_alt"),
+        }
+    }
+
+    /// Machine generated values. User edits live in the `user_*` columns.
     pub async fn link_genai_descriptors(
         &self,
         conn: &DbConn,
@@ -290,6 +450,47 @@ impl GalleryEmbeddings {
         Ok(())
     }
 
+    /// Stores the user edits apart from the AI values, `link_genai_descriptors`
+    /// never touches them.
+    /// Returns false when the photo is not found for the user or is not processed yet.
+    pub async fn link_user_descriptors(
+        conn: &DbConn,
+        user_id: &str,
+        gallery_id: &Uuid,
+        edits: &DescriptorEdits,
+    ) -> Result<bool, QueryError> {
+        let reset = |field| edits.reset.contains(&field);
+        let updated = sqlx::query(
+            r#"
+              UPDATE gallery_rag_embeddings ge SET
+                  user_keywords = CASE WHEN $3 THEN NULL ELSE coalesce($4, ge.user_keywords) END,
+                  user_description = CASE WHEN $5 THEN NULL ELSE coalesce($6, ge.user_description) END,
+                  user_theme = CASE WHEN $7 THEN NULL ELSE coalesce($8, ge.user_theme) END,
+                  user_img_alt = CASE WHEN $9 THEN NULL ELSE coalesce($10, ge.user_img_alt) END,
+                  user_img_aria = CASE WHEN $11 THEN NULL ELSE coalesce($12, ge.user_img_aria) END,
+                  user_edited_at = now()
+              FROM gallery g join user_upload u on u.gallery_id=g.id
+              WHERE g.embeddings_id = ge.id and g.id = $2 and u.user_id = $1
+          "#,
+        )
+        .bind(user_id)
+        .bind(gallery_id)
+        .bind(reset(DescriptorField::Keywords))
+        .bind(&edits.keywords)
+        .bind(reset(DescriptorField::Description))
+        .bind(&edits.description)
+        .bind(reset(DescriptorField::Theme))
+        .bind(&edits.theme)
+        .bind(reset(DescriptorField::ImgAlt))
+        .bind(&edits.img_alt)
+        .bind(reset(DescriptorField::ImgAria))
+        .bind(&edits.img_aria)
+        .execute(conn)
+        .await?;
+
+        Ok(updated.rows_affected() > 0)
+    }
+
     // Deletes
     pub async fn delete_one(self, conn: &crate::DbConn) -> Result<(), QueryError> {
          sqlx::query!(
@@ -333,6 +534,111 @@ impl UserUpload {
         Ok(user_upload)
     }
 
+    /// Pending uploads of the file hashes, without a gallery entry and
+    /// untouched for `stale_after_secs`. The upload url expired unused or the
+    /// bucket event is still queued, the caller tells them apart by looking
+    /// for the file in the bucket.
+    pub async fn stale_pending(
+        conn: &crate::DbConn,
+        user_id: &str,
+        filehashes: &[String],
+        stale_after_secs: i64,
+    ) -> QueryResult<Vec<UserUpload>> {
+        let filehashes: Vec<String> = filehashes.iter().map(|h| h.to_lowercase()).collect();
+        Ok(sqlx::query_as::<_, UserUpload>(
+            "
+            SELECT id, filename, filesize::bigint as filesize, filehash, user_id, gallery_id
+            from user_upload
+            where user_id=$1 and filehash = any($2) and duplicate_of is null and gallery_id is null
+                and processing_status = 'pending'
+                and processing_updated_at < now() - $3 * interval '1 second'
+            ",
+        )
+        .bind(user_id)
+        .bind(&filehashes)
+        .bind(stale_after_secs)
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Creates the uploads of a batch in a single round trip.
+    /// Uploads are unique per user and file hash, the unique index settles
+    /// concurrent requests. An upload without a gallery entry can be requested
+    /// again, it keeps its storage key, when it failed or when it is one of
+    /// the `reissued` pending uploads, see `stale_pending`. Uploaded files are
+    /// being processed and never requested again.
+    /// Returns, in the request order, the new upload or why it was rejected.
+    pub async fn new_for_uploads(
+        conn: &crate::DbConn,
+        user_id: &str,
+        uploads: &[NewUpload<'_>],
+        reissued: &[Uuid],
+    ) -> QueryResult<Vec<Result<UserUpload, UploadRejection>>> {
+        let names: Vec<&str> = uploads.iter().map(|u| u.name).collect();
+        let filesizes: Vec<i32> = uploads.iter().map(|u| u.filesize).collect();
+        let filehashes: Vec<String> = uploads.iter().map(|u| u.filehash.to_lowercase()).collect();
+
+        let created: Vec<(i64, Option<Uuid>, Option<String>, bool)> = sqlx::query_as(
+            "
+            with requested as (
+                SELECT r.original_filename, r.filesize, r.filehash, r.idx
+                from unnest($2::text[], $3::int[], $4::text[]) with ordinality r(original_filename, filesize, filehash, idx)
+            ),
+            first_requested as (
+                SELECT distinct on (filehash) original_filename, filesize, filehash, idx
+                from requested order by filehash, idx
+            ),
+            new_uploads as (
+                SELECT gen_random_uuid() as id, f.* from first_requested f
+            ),
+            inserted as (
+                INSERT into user_upload(id, filename, original_filename, filesize, filehash, user_id)
+                SELECT n.id, 'feeder/' || n.id, n.original_filename, n.filesize, n.filehash, $1
+                from new_uploads n
+                order by n.idx
+                on conflict (user_id, filehash) where duplicate_of is null do update
+                    SET original_filename=excluded.original_filename, filesize=excluded.filesize,
+                        processing_status='pending', processing_error=null,
+                        processing_updated_at=now(), updated_at=now()
+                    where user_upload.gallery_id is null and (
+                        user_upload.processing_status = 'failed'
+                        or (user_upload.processing_status = 'pending' and user_upload.id = any($5))
+                    )
+                returning id, filename, filehash
+            )
+            SELECT r.idx, i.id, i.filename, f.idx is not null as first_in_batch
+            from requested r
+                left join first_requested f on f.idx = r.idx
+                left join inserted i on i.filehash = f.filehash
+            order by r.idx
+            ",
+        )
+        .bind(user_id)
+        .bind(names)
+        .bind(filesizes)
+        .bind(&filehashes)
+        .bind(reissued)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(created
+            .into_iter()
+            .zip(uploads.iter().zip(filehashes))
+            .map(|((_, id, filename, first_in_batch), (upload, filehash))| match (id, filename) {
+                (Some(id), Some(filename)) => Ok(UserUpload {
+                    id,
+                    filename,
+                    filesize: upload.filesize as i64,
+                    filehash,
+                    user_id: Some(user_id.to_string()),
+                    gallery_id: None,
+                }),
+                _ if first_in_batch => Err(UploadRejection::Duplicated),
+                _ => Err(UploadRejection::RepeatedInBatch),
+            })
+            .collect())
+    }
+
     pub async fn get_by_filename(conn: &crate::DbConn, filename: &str) -> Result<Self, QueryError> {
         let user_upload = sqlx::query_as!(UserUpload, r#"
             SELECT id, filename, filesize, filehash, user_id, gallery_id from user_upload where filename = $1"#, filename).fetch_one(conn).await.map_err(|e| {log::error!("{e:?}"); QueryError::Query})?;
@@ -455,20 +761,157 @@ mod tests {
 
     #[tokio::test]
     async fn it_finds_gallery_with_embeddings() {
-        let (conn, gallery_itm) = create_reg().await;
+        let (conn, mut gallery_itm) = create_reg().await;
+        let user_id = format!("test-{}", Uuid::new_v4());
 
-        let embe = GalleryEmbeddings::find_nearest(vec![1.0; 512], &conn)
+        let mut upload = UserUpload::new_for_upload(&conn, "feeder/nearest.jpg", 10, "hash", &user_id)
             .await
             .unwrap();
-        println!("{:?}", embe);
+        upload.set_gallery_id(&conn, gallery_itm.id()).await.unwrap();
 
-        assert!(embe.len() > 1);
+        let mut embe = GalleryEmbeddings::new("/some/thumbnail.webp".into(), vec![1.0; 512]);
+        embe.create(&conn).await.unwrap();
+        let thumbnail = NewThumbnail{
+                        path: "/some/thumbnailpath.jpg", height: 3, width: 4, ratio: "portrait" };
+        gallery_itm
+            .update_with_processed(&conn, "/new/some/nearest.jpg", thumbnail, NewEmbeddings{embeddings_id: embe.id()})
+            .await
+            .unwrap();
+
+        let nearest = GalleryEmbeddings::find_nearest(&conn, &user_id, vec![1.0; 512], &NearestQuery::default())
+            .await
+            .unwrap();
+        println!("{:?}", nearest);
+
+        assert!(nearest.len() == 1);
+        assert!(nearest[0].id() == embe.id());
+
+        // Other users never see it
+        let others = GalleryEmbeddings::find_nearest(&conn, "someone-else", vec![1.0; 512], &NearestQuery::default())
+            .await
+            .unwrap();
+        assert!(others.iter().all(|e| e.id() != embe.id()));
 
         // Clean after
+        let _ = sqlx::query("DELETE from user_upload where id=$1").bind(upload.id()).execute(&conn).await;
         let _ = gallery_itm.delete_one(&conn).await;
-        for e in embe.iter().to_owned() {
-        let _ = <GalleryEmbeddings as Clone>::clone(&e).delete_one(&conn).await;
-        }
+        let _ = embe.delete_one(&conn).await;
+    }
+
+    #[tokio::test]
+    async fn it_requests_a_stale_pending_upload_again() {
+        let postgres_url = std::env!("DATABASE_URL");
+        let conn = crate::db_connect(postgres_url).await.unwrap();
+        let user_id = format!("test-{}", Uuid::new_v4());
+        let filehash = format!("{:064}", 1);
+        let uploads = [NewUpload { name: "stale.jpg", filesize: 10, filehash: &filehash }];
+
+        let first = UserUpload::new_for_uploads(&conn, &user_id, &uploads, &[])
+            .await
+            .unwrap()
+            .remove(0)
+            .unwrap();
+
+        // Still within the upload url lifetime
+        let stale = UserUpload::stale_pending(&conn, &user_id, &[filehash.clone()], 3600).await.unwrap();
+        assert!(stale.is_empty());
+        let repeated = UserUpload::new_for_uploads(&conn, &user_id, &uploads, &[]).await.unwrap();
+        assert!(matches!(repeated[0], Err(UploadRejection::Duplicated)));
+
+        sqlx::query("UPDATE user_upload SET processing_updated_at=now() - interval '2 hours' where id=$1")
+            .bind(first.id())
+            .execute(&conn)
+            .await
+            .unwrap();
+        let stale = UserUpload::stale_pending(&conn, &user_id, &[filehash.clone()], 3600).await.unwrap();
+        assert_eq!(stale.len(), 1);
+        let again = UserUpload::new_for_uploads(&conn, &user_id, &uploads, &[*stale[0].id()])
+            .await
+            .unwrap()
+            .remove(0)
+            .unwrap();
+        assert_eq!(again.id(), first.id());
+        assert_eq!(again.filename(), first.filename());
+
+        // Uploaded files are being processed, however long it takes
+        sqlx::query(
+            "UPDATE user_upload SET processing_status='uploaded', processing_updated_at=now() - interval '2 hours' where id=$1",
+        )
+        .bind(first.id())
+        .execute(&conn)
+        .await
+        .unwrap();
+        let stale = UserUpload::stale_pending(&conn, &user_id, &[filehash.clone()], 3600).await.unwrap();
+        assert!(stale.is_empty());
+        let processing = UserUpload::new_for_uploads(&conn, &user_id, &uploads, &[*first.id()]).await.unwrap();
+        assert!(matches!(processing[0], Err(UploadRejection::Duplicated)));
+
+        // Clean after
+        let _ = sqlx::query("DELETE from user_upload where user_id=$1").bind(&user_id).execute(&conn).await;
+    }
+
+    #[tokio::test]
+    async fn it_migrates_processed_duplicate_uploads() {
+        let postgres_url = std::env!("DATABASE_URL");
+        let conn = crate::db_connect(postgres_url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        // Tables as they were before the migration. The temporary ones
+        // shadow the real tables and are gone with the rollback.
+        sqlx::raw_sql(
+            "
+            CREATE TEMP TABLE gallery(id uuid primary key, path text not null) ON COMMIT DROP;
+            CREATE TEMP TABLE user_upload(
+                id uuid primary key default gen_random_uuid(),
+                filename text not null,
+                filesize int not null,
+                filehash text not null,
+                user_id text,
+                gallery_id uuid,
+                created_at timestamptz not null default now()
+            ) ON COMMIT DROP;
+            INSERT into gallery values
+                ('00000000-0000-0000-0000-000000000001', 'older.jpg'),
+                ('00000000-0000-0000-0000-000000000002', 'newer.jpg');
+            INSERT into user_upload(filename, filesize, filehash, user_id, gallery_id, created_at) values
+                ('feeder/older.jpg', 1, 'hash', 'user', '00000000-0000-0000-0000-000000000001', now() - interval '1 day'),
+                ('feeder/newer.jpg', 1, 'hash', 'user', '00000000-0000-0000-0000-000000000002', now());
+            ",
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+        sqlx::raw_sql(include_str!("../migrations/25110012_add-user-filehash-dedupe.sql"))
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        let uploads: Vec<(String, bool, bool)> = sqlx::query_as(
+            "
+            SELECT u.filename, u.duplicate_of is not null, g.archived_at is not null
+            from user_upload u join gallery g on g.id = u.gallery_id
+            order by u.filename
+            ",
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .unwrap();
+        assert_eq!(
+            uploads,
+            vec![
+                ("feeder/newer.jpg".to_string(), true, true),
+                ("feeder/older.jpg".to_string(), false, false),
+            ]
+        );
+
+        // The unique index is built and still rejects new duplicates
+        let repeated = sqlx::query(
+            "INSERT into user_upload(filename, filesize, filehash, user_id) values ('feeder/again.jpg', 1, 'hash', 'user')",
+        )
+        .execute(&mut *tx)
+        .await;
+        assert!(repeated.is_err());
     }
 
     #[tokio::test]
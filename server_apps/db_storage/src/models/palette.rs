@@ -0,0 +1,323 @@
+use std::str::FromStr;
+
+use crate::errors::QueryResult;
+
+/// Share of the photo a named colour needs to match the filter and count in
+/// its facet. Several palette colours of the same name add up.
+pub const NAMED_COLOR_MIN_SHARE: f32 = 0.2;
+/// Share of the photo a palette colour needs to match a hex filter.
+pub const NEAR_COLOR_MIN_SHARE: f32 = 0.05;
+/// CIE76 distance accepted by a hex filter when none is given.
+/// Around 2.3 is the smallest difference people notice.
+pub const DEFAULT_COLOR_TOLERANCE: f32 = 15.0;
+
+/// D65 white point
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+const EPSILON: f32 = 6.0 / 29.0;
+
+/// Colour in the CIELAB space, D65 white point. Euclidean distances roughly
+/// match the perceived difference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Lab {
+    pub fn from_rgb(rgb: [u8; 3]) -> Self {
+        let [r, g, b] = rgb.map(srgb_to_linear);
+        let xyz = [
+            0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+            0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+            0.0193339 * r + 0.119192 * g + 0.9503041 * b,
+        ];
+        let f = |t: f32| {
+            if t > EPSILON.powi(3) {
+                t.cbrt()
+            } else {
+                t / (3.0 * EPSILON.powi(2)) + 4.0 / 29.0
+            }
+        };
+        let [fx, fy, fz] = [0, 1, 2].map(|i| f(xyz[i] / WHITE[i]));
+
+        Self {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+
+    /// Closest sRGB colour, the ones out of the gamut are clamped.
+    pub fn to_rgb(&self) -> [u8; 3] {
+        let fy = (self.l + 16.0) / 116.0;
+        let f_inverse = |t: f32| {
+            if t > EPSILON {
+                t.powi(3)
+            } else {
+                3.0 * EPSILON.powi(2) * (t - 4.0 / 29.0)
+            }
+        };
+        let [fx, fz] = [fy + self.a / 500.0, fy - self.b / 200.0];
+        let [x, y, z] = [
+            f_inverse(fx) * WHITE[0],
+            f_inverse(fy) * WHITE[1],
+            f_inverse(fz) * WHITE[2],
+        ];
+
+        [
+            3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+            -0.969266 * x + 1.8760108 * y + 0.041556 * z,
+            0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+        ]
+        .map(linear_to_srgb)
+    }
+
+    /// `#rrggbb`, with or without the `#`.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim().trim_start_matches('#');
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        Some(Self::from_rgb([channel(0)?, channel(2)?, channel(4)?]))
+    }
+
+    pub fn to_hex(&self) -> String {
+        let [r, g, b] = self.to_rgb();
+        format!("#{r:02x}{g:02x}{b:02x}")
+    }
+
+    /// CIE76 colour difference.
+    pub fn distance(&self, other: &Lab) -> f32 {
+        ((self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2))
+            .sqrt()
+    }
+
+    /// Name people would give to the colour. Greys go by their lightness,
+    /// the other colours by their hue angle.
+    pub fn name(&self) -> ColorName {
+        let chroma = self.a.hypot(self.b);
+        if self.l < 10.0 {
+            return ColorName::Black;
+        }
+        if chroma < 10.0 {
+            return match self.l {
+                l if l < 20.0 => ColorName::Black,
+                l if l > 85.0 => ColorName::White,
+                _ => ColorName::Gray,
+            };
+        }
+
+        match self.b.atan2(self.a).to_degrees().rem_euclid(360.0) {
+            h if !(20.0..345.0).contains(&h) => ColorName::Pink,
+            h if h < 50.0 => ColorName::Red,
+            h if h < 75.0 && self.l < 55.0 => ColorName::Brown,
+            h if h < 75.0 => ColorName::Orange,
+            h if h < 110.0 && self.l < 45.0 => ColorName::Brown,
+            h if h < 110.0 => ColorName::Yellow,
+            h if h < 165.0 => ColorName::Green,
+            h if h < 230.0 => ColorName::Teal,
+            h if h < 310.0 => ColorName::Blue,
+            _ => ColorName::Purple,
+        }
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let v = value.clamp(0.0, 1.0);
+    let v = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (v * 255.0).round() as u8
+}
+
+/// Colour families offered by the gallery filter, as stored in
+/// `embeddings_palette.color_name`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum ColorName {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Teal,
+    Blue,
+    Purple,
+    Pink,
+    Brown,
+    Black,
+    Gray,
+    White,
+}
+
+impl ColorName {
+    pub const ALL: [ColorName; 12] = [
+        ColorName::Red,
+        ColorName::Orange,
+        ColorName::Yellow,
+        ColorName::Green,
+        ColorName::Teal,
+        ColorName::Blue,
+        ColorName::Purple,
+        ColorName::Pink,
+        ColorName::Brown,
+        ColorName::Black,
+        ColorName::Gray,
+        ColorName::White,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorName::Red => "red",
+            ColorName::Orange => "orange",
+            ColorName::Yellow => "yellow",
+            ColorName::Green => "green",
+            ColorName::Teal => "teal",
+            ColorName::Blue => "blue",
+            ColorName::Purple => "purple",
+            ColorName::Pink => "pink",
+            ColorName::Brown => "brown",
+            ColorName::Black => "black",
+            ColorName::Gray => "gray",
+            ColorName::White => "white",
+        }
+    }
+}
+
+impl FromStr for ColorName {
+    type Err = ();
+
+    /// Case insensitive, `grey` is accepted too.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        let name = if name == "grey" { "gray" } else { &name };
+        ColorName::ALL
+            .into_iter()
+            .find(|color| color.as_str() == name)
+            .ok_or(())
+    }
+}
+
+/// Colour filter of the gallery listing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorFilter {
+    /// Photos where the colour family covers at least `NAMED_COLOR_MIN_SHARE`.
+    Named(ColorName),
+    /// Photos with a palette colour within `tolerance` of `color`.
+    Near { color: Lab, tolerance: f32 },
+}
+
+impl ColorFilter {
+    pub fn name(&self) -> Option<ColorName> {
+        match self {
+            ColorFilter::Named(name) => Some(*name),
+            ColorFilter::Near { .. } => None,
+        }
+    }
+
+    /// `[l, a, b]` of a hex filter.
+    pub fn lab(&self) -> Option<Vec<f32>> {
+        match self {
+            ColorFilter::Near { color, .. } => Some(vec![color.l, color.a, color.b]),
+            ColorFilter::Named(_) => None,
+        }
+    }
+
+    pub fn tolerance(&self) -> Option<f32> {
+        match self {
+            ColorFilter::Near { tolerance, .. } => Some(*tolerance),
+            ColorFilter::Named(_) => None,
+        }
+    }
+}
+
+/// Dominant colour of a photo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteColor {
+    pub color: Lab,
+    /// Fraction of the pixels, from 0 to 1.
+    pub share: f32,
+}
+
+impl PaletteColor {
+    /// Replaces the palette of the embeddings. `colors` goes most dominant first.
+    pub async fn save_for_embeddings(
+        conn: &crate::DbConn,
+        embeddings_id: i64,
+        colors: &[PaletteColor],
+    ) -> QueryResult<()> {
+        let mut tx = conn.begin().await?;
+        sqlx::query("DELETE from embeddings_palette where embeddings_id=$1")
+            .bind(embeddings_id)
+            .execute(&mut *tx)
+            .await?;
+        for (position, palette_color) in colors.iter().enumerate() {
+            let color = palette_color.color;
+            sqlx::query(
+                "INSERT into embeddings_palette(embeddings_id, position, lab_l, lab_a, lab_b, hex, color_name, share)
+                values ($1, $2, $3, $4, $5, $6, $7, $8)",
+            )
+            .bind(embeddings_id)
+            .bind(position as i32)
+            .bind(color.l)
+            .bind(color.a)
+            .bind(color.b)
+            .bind(color.to_hex())
+            .bind(color.name())
+            .bind(palette_color.share)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_converts_to_lab_and_back() {
+        let white = Lab::from_rgb([255, 255, 255]);
+        assert!((white.l - 100.0).abs() < 0.01);
+        assert!(white.a.abs() < 0.01 && white.b.abs() < 0.01);
+
+        let dodger_blue = Lab::from_hex("#1E90FF").unwrap();
+        assert_eq!(dodger_blue.to_hex(), "#1e90ff");
+        assert!(Lab::from_hex("blue").is_none());
+        assert!(Lab::from_hex("#12345").is_none());
+    }
+
+    #[test]
+    fn it_names_colors() {
+        let name = |hex: &str| Lab::from_hex(hex).unwrap().name();
+        assert_eq!(name("#ff0000"), ColorName::Red);
+        assert_eq!(name("#ffa500"), ColorName::Orange);
+        assert_eq!(name("#ffff00"), ColorName::Yellow);
+        assert_eq!(name("#008000"), ColorName::Green);
+        assert_eq!(name("#008080"), ColorName::Teal);
+        assert_eq!(name("#1e90ff"), ColorName::Blue);
+        assert_eq!(name("#000080"), ColorName::Blue);
+        assert_eq!(name("#800080"), ColorName::Purple);
+        assert_eq!(name("#ff69b4"), ColorName::Pink);
+        assert_eq!(name("#8b4513"), ColorName::Brown);
+        assert_eq!(name("#808080"), ColorName::Gray);
+        assert_eq!(name("#fafafa"), ColorName::White);
+        assert_eq!(name("#050505"), ColorName::Black);
+        assert_eq!("Grey".parse(), Ok(ColorName::Gray));
+    }
+}
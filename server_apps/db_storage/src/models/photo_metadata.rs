@@ -0,0 +1,92 @@
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use crate::errors::QueryResult;
+
+/// Metadata embedded in the uploaded file. Missing tags are None.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PhotoMetadata {
+    /// Camera wall clock.
+    pub taken_at: Option<PrimitiveDateTime>,
+    /// Minutes east of UTC of `taken_at`.
+    pub taken_at_offset: Option<i16>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens_model: Option<String>,
+    /// Seconds
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    /// Millimeters
+    pub focal_length: Option<f64>,
+    pub iso: Option<i32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Meters over the sea level
+    pub altitude: Option<f64>,
+    /// EXIF orientation, 1 to 8.
+    pub orientation: Option<i16>,
+    /// IPTC and XMP keywords
+    pub keywords: Vec<String>,
+    /// IPTC and XMP caption
+    pub caption: Option<String>,
+    /// Place closest to the GPS coordinates.
+    pub city: Option<String>,
+    pub region: Option<String>,
+    pub country: Option<String>,
+}
+
+impl PhotoMetadata {
+    /// Embedded keywords followed by the place names.
+    pub fn tags(&self) -> Vec<String> {
+        let places = [&self.city, &self.region, &self.country];
+        self.keywords
+            .iter()
+            .cloned()
+            .chain(places.into_iter().flatten().cloned())
+            .collect()
+    }
+
+    /// Replaces the metadata of the gallery item. Reprocessing overwrites it.
+    pub async fn save(&self, conn: &crate::DbConn, gallery_id: &Uuid) -> QueryResult<()> {
+        sqlx::query(
+            "
+            INSERT into photo_metadata(gallery_id, taken_at, taken_at_offset, camera_make, camera_model,
+                lens_model, exposure_time, f_number, focal_length, iso, latitude, longitude, altitude,
+                orientation, keywords, caption, city, region, country)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)
+            on conflict (gallery_id) do update SET
+                taken_at=excluded.taken_at, taken_at_offset=excluded.taken_at_offset,
+                camera_make=excluded.camera_make, camera_model=excluded.camera_model,
+                lens_model=excluded.lens_model, exposure_time=excluded.exposure_time,
+                f_number=excluded.f_number, focal_length=excluded.focal_length, iso=excluded.iso,
+                latitude=excluded.latitude, longitude=excluded.longitude, altitude=excluded.altitude,
+                orientation=excluded.orientation, keywords=excluded.keywords, caption=excluded.caption,
+                city=excluded.city, region=excluded.region, country=excluded.country,
+                updated_at=now()
+            ",
+        )
+        .bind(gallery_id)
+        .bind(self.taken_at)
+        .bind(self.taken_at_offset)
+        .bind(&self.camera_make)
+        .bind(&self.camera_model)
+        .bind(&self.lens_model)
+        .bind(self.exposure_time)
+        .bind(self.f_number)
+        .bind(self.focal_length)
+        .bind(self.iso)
+        .bind(self.latitude)
+        .bind(self.longitude)
+        .bind(self.altitude)
+        .bind(self.orientation)
+        .bind(&self.keywords)
+        .bind(&self.caption)
+        .bind(&self.city)
+        .bind(&self.region)
+        .bind(&self.country)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
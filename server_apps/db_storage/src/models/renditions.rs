@@ -0,0 +1,115 @@
+use derive_getters::Getters;
+use uuid::Uuid;
+
+use crate::errors::QueryResult;
+
+/// Encoding of a rendition, as stored in `rendition.format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum RenditionFormat {
+    Webp,
+    Avif,
+    Jpeg,
+    /// Preview of an animated image.
+    #[sqlx(rename = "webp_animated")]
+    AnimatedWebp,
+}
+
+impl RenditionFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RenditionFormat::Webp | RenditionFormat::AnimatedWebp => "webp",
+            RenditionFormat::Avif => "avif",
+            RenditionFormat::Jpeg => "jpg",
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        matches!(self, RenditionFormat::AnimatedWebp)
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            RenditionFormat::Webp | RenditionFormat::AnimatedWebp => "image/webp",
+            RenditionFormat::Avif => "image/avif",
+            RenditionFormat::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+/// Resized copy of a photo in the ragged bucket.
+#[derive(Debug, Clone, Getters, sqlx::FromRow)]
+pub struct Rendition {
+    gallery_id: Uuid,
+    #[getter(copy)]
+    format: RenditionFormat,
+    width: i32,
+    height: i32,
+    /// Bucket path, or its signed url once `set_signed_url` is called.
+    path: String,
+}
+
+impl Rendition {
+    pub fn new(
+        gallery_id: Uuid,
+        format: RenditionFormat,
+        width: i32,
+        height: i32,
+        path: String,
+    ) -> Self {
+        Self {
+            gallery_id,
+            format,
+            width,
+            height,
+            path,
+        }
+    }
+
+    /// Records the rendition. A reprocessed photo replaces the previous path.
+    pub async fn save(&self, conn: &crate::DbConn) -> QueryResult<()> {
+        sqlx::query(
+            "
+            INSERT into rendition(gallery_id, format, width, height, path)
+            values ($1, $2, $3, $4, $5)
+            on conflict (gallery_id, format, width) do update
+                SET height=excluded.height, path=excluded.path
+            ",
+        )
+        .bind(self.gallery_id)
+        .bind(self.format)
+        .bind(self.width)
+        .bind(self.height)
+        .bind(&self.path)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Renditions of the gallery items, grouped by item and smallest first.
+    /// The ids are expected to come from a query already scoped to the user.
+    pub async fn list_for_galleries(
+        conn: &crate::DbConn,
+        gallery_ids: &[Uuid],
+    ) -> QueryResult<Vec<Self>> {
+        if gallery_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        Ok(sqlx::query_as::<_, Rendition>(
+            "
+            SELECT gallery_id, format, width, height, path
+            from rendition
+            where gallery_id = any($1)
+            order by gallery_id, format, width
+            ",
+        )
+        .bind(gallery_ids)
+        .fetch_all(conn)
+        .await?)
+    }
+
+    pub fn set_signed_url(&mut self, url: String) {
+        self.path = url;
+    }
+}
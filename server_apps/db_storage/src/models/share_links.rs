@@ -0,0 +1,192 @@
+use derive_getters::Getters;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::errors::QueryResult;
+
+/// What a share link opens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShareTarget {
+    Photo(Uuid),
+    Album(Uuid),
+}
+
+/// Public link as seen by its owner.
+#[derive(Debug, Getters, sqlx::FromRow)]
+pub struct ShareLink {
+    id: Uuid,
+    token: String,
+    gallery_id: Option<Uuid>,
+    album_id: Option<Uuid>,
+    expires_at: OffsetDateTime,
+    /// Unlimited when None
+    max_views: Option<i32>,
+    views: i32,
+    revoked_at: Option<OffsetDateTime>,
+    created_at: OffsetDateTime,
+}
+
+/// Photo served through a share link. Nothing here identifies the owner.
+#[derive(Debug, Getters, sqlx::FromRow)]
+pub struct SharedPhoto {
+    /// Original image bucket path
+    path: String,
+    thumbnail_path: Option<String>,
+    thumbnail_ratio: Option<String>,
+    img_alt: Option<String>,
+    description: Option<String>,
+}
+
+/// Content of a valid share link.
+#[derive(Debug, Getters)]
+pub struct SharedContent {
+    /// Album name. None for a single photo.
+    title: Option<String>,
+    photos: Vec<SharedPhoto>,
+    expires_at: OffsetDateTime,
+}
+
+impl SharedContent {
+    pub fn photos_mut(&mut self) -> &mut Vec<SharedPhoto> {
+        &mut self.photos
+    }
+}
+
+impl ShareLink {
+    /// None when the photo or album does not exist or belongs to someone else.
+    pub async fn create(
+        conn: &crate::DbConn,
+        user_id: &str,
+        token: &str,
+        target: &ShareTarget,
+        expires_at: OffsetDateTime,
+        max_views: Option<i32>,
+    ) -> QueryResult<Option<Self>> {
+        let (gallery_id, album_id) = match target {
+            ShareTarget::Photo(id) => (Some(*id), None),
+            ShareTarget::Album(id) => (None, Some(*id)),
+        };
+
+        Ok(sqlx::query_as::<_, ShareLink>(
+            "
+            INSERT into share_link(token, user_id, gallery_id, album_id, expires_at, max_views)
+            SELECT $1, $2, $3, $4, $5, $6
+            where exists (SELECT 1 from user_upload u where u.user_id=$2 and u.gallery_id=$3)
+                or exists (SELECT 1 from album a where a.user_id=$2 and a.id=$4)
+            returning id, token, gallery_id, album_id, expires_at, max_views, views, revoked_at, created_at
+            ",
+        )
+        .bind(token)
+        .bind(user_id)
+        .bind(gallery_id)
+        .bind(album_id)
+        .bind(expires_at)
+        .bind(max_views)
+        .fetch_optional(conn)
+        .await?)
+    }
+
+    /// Links of the user, newest first. Includes expired and revoked ones.
+    pub async fn list_for_user(conn: &crate::DbConn, user_id: &str) -> QueryResult<Vec<Self>> {
+        Ok(sqlx::query_as::<_, ShareLink>(
+            "
+            SELECT id, token, gallery_id, album_id, expires_at, max_views, views, revoked_at, created_at
+            from share_link where user_id=$1
+            order by created_at desc
+            ",
+        )
+        .bind(user_id)
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// False when the link is not found or already revoked.
+    pub async fn revoke(conn: &crate::DbConn, user_id: &str, id: &Uuid) -> QueryResult<bool> {
+        let revoked = sqlx::query(
+            "UPDATE share_link SET revoked_at=now() where id=$1 and user_id=$2 and revoked_at is null",
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(conn)
+        .await?;
+        Ok(revoked.rows_affected() > 0)
+    }
+
+    /// Returns the shared photos, counting a view when `count_view` is set.
+    /// None when the token is unknown, expired, revoked or out of views.
+    pub async fn resolve(
+        conn: &crate::DbConn,
+        token: &str,
+        count_view: bool,
+    ) -> QueryResult<Option<SharedContent>> {
+        // The view is counted in the same statement that checks the limit,
+        // concurrent requests can not go over it.
+        let link: Option<(String, Option<Uuid>, Option<Uuid>, OffsetDateTime)> = sqlx::query_as(
+            "
+            UPDATE share_link SET views=views + (case when $2 then 1 else 0 end)
+            where token=$1 and revoked_at is null and expires_at > now()
+                and (max_views is null or views < max_views)
+            returning user_id, gallery_id, album_id, expires_at
+            ",
+        )
+        .bind(token)
+        .bind(count_view)
+        .fetch_optional(conn)
+        .await?;
+        let (user_id, gallery_id, album_id, expires_at) = match link {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+
+        let title: Option<(String,)> = match album_id {
+            Some(album_id) => {
+                sqlx::query_as("SELECT name from album where id=$1")
+                    .bind(album_id)
+                    .fetch_optional(conn)
+                    .await?
+            }
+            None => None,
+        };
+
+        // The owner check drops photos deleted or moved out of the album
+        // after the link was created. Archived photos are not shared.
+        let photos = sqlx::query_as::<_, SharedPhoto>(
+            "
+            SELECT g.path, g.thumbnail_path, g.thumbnail_ratio,
+                coalesce(ge.user_img_alt, ge.img_alt) as img_alt,
+                coalesce(ge.user_description, ge.description) as description
+            from gallery g
+                join user_upload u on u.gallery_id=g.id
+                left join gallery_rag_embeddings ge on g.embeddings_id = ge.id
+            where u.user_id=$1 and g.thumbnail_path is not null and g.archived_at is null
+                and (g.id = $2 or exists (
+                    SELECT 1 from album_item ai where ai.gallery_id = g.id and ai.album_id = $3
+                ))
+            order by g.created_at desc, g.id desc
+            ",
+        )
+        .bind(&user_id)
+        .bind(gallery_id)
+        .bind(album_id)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(Some(SharedContent {
+            title: title.map(|t| t.0),
+            photos,
+            expires_at,
+        }))
+    }
+}
+
+impl SharedPhoto {
+    pub fn set_signed_urls(&mut self, original: String, thumbnail: String) {
+        self.path = original;
+        self.thumbnail_path = Some(thumbnail);
+    }
+
+    /// The original keeps its bucket path, for pages that do not link it.
+    pub fn set_signed_thumbnail(&mut self, thumbnail: String) {
+        self.thumbnail_path = Some(thumbnail);
+    }
+}
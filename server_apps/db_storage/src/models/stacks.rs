@@ -0,0 +1,168 @@
+use derive_getters::Getters;
+use uuid::Uuid;
+
+use crate::errors::QueryResult;
+use crate::models::user_photos::UserPhoto;
+
+/// Seconds between the capture times of two frames of a burst.
+pub const BURST_WINDOW_SECONDS: f64 = 2.0;
+/// Cosine distance up to which two frames of a burst look alike.
+pub const BURST_MAX_DISTANCE: f64 = 0.1;
+
+/// Why the photos were stacked, as stored in `photo_stack.kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum StackKind {
+    /// Same file name and capture time, e.g. `IMG_1234.CR2` and `IMG_1234.JPG`.
+    RawJpeg,
+    /// Look-alike frames from the same camera taken in quick succession.
+    Burst,
+}
+
+#[derive(sqlx::FromRow)]
+struct StackCandidate {
+    gallery_id: Uuid,
+    stack_id: Option<Uuid>,
+    kind: StackKind,
+}
+
+/// Related photos shown as a single gallery entry, its cover.
+#[derive(Debug, Clone, Getters)]
+pub struct PhotoStack {
+    id: Uuid,
+    #[getter(copy)]
+    kind: StackKind,
+}
+
+impl PhotoStack {
+    /// Stacks a processed photo with its closest related photo of the same user.
+    /// A RAW+JPEG sibling is preferred over a burst frame. The photo joins the
+    /// sibling stack, or both start a new one. None when nothing is related.
+    /// Needs the photo embeddings and metadata to be stored.
+    pub async fn assign(
+        conn: &crate::DbConn,
+        gallery_id: &Uuid,
+        burst_window_seconds: f64,
+        burst_max_distance: f64,
+    ) -> QueryResult<Option<Self>> {
+        let mut tx = conn.begin().await?;
+        let candidate = sqlx::query_as::<_, StackCandidate>(
+            "
+            with photo as (
+                SELECT g.id, u.user_id, pm.taken_at, pm.camera_make, pm.camera_model, ge.embedding,
+                    regexp_replace(lower(u.original_filename), '\\.[^./]*$', '') as basename
+                from gallery g
+                    join user_upload u on u.gallery_id = g.id
+                    join photo_metadata pm on pm.gallery_id = g.id
+                    left join gallery_rag_embeddings ge on ge.id = g.embeddings_id
+                where g.id = $1 and pm.taken_at is not null
+            )
+            SELECT o.id as gallery_id, o.stack_id,
+                case when c.same_name then 'raw_jpeg' else 'burst' end as kind
+            from photo p
+                join user_upload ou on ou.user_id = p.user_id and ou.gallery_id <> p.id
+                join gallery o on o.id = ou.gallery_id and o.archived_at is null
+                join photo_metadata opm on opm.gallery_id = o.id
+                left join gallery_rag_embeddings oge on oge.id = o.embeddings_id
+                cross join lateral (
+                    SELECT coalesce(regexp_replace(lower(ou.original_filename), '\\.[^./]*$', '') = p.basename, false) as same_name,
+                        abs(extract(epoch from opm.taken_at - p.taken_at)) as seconds_apart
+                ) c
+            where opm.taken_at between p.taken_at - make_interval(secs => $2) and p.taken_at + make_interval(secs => $2)
+                and (
+                    (c.same_name and c.seconds_apart <= 1)
+                    or (opm.camera_make is not distinct from p.camera_make
+                        and opm.camera_model is not distinct from p.camera_model
+                        and oge.embedding <=> p.embedding <= $3)
+                )
+            order by c.same_name desc, c.seconds_apart, o.id
+            limit 1
+            ",
+        )
+        .bind(gallery_id)
+        .bind(burst_window_seconds)
+        .bind(burst_max_distance)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let candidate = match candidate {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let stack_id: Uuid = match candidate.stack_id {
+            Some(id) => id,
+            None => {
+                sqlx::query_scalar(
+                    "
+                    INSERT into photo_stack(user_id, kind)
+                    SELECT user_id, $2 from user_upload where gallery_id=$1
+                    returning id
+                    ",
+                )
+                .bind(gallery_id)
+                .bind(candidate.kind)
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+        sqlx::query("UPDATE gallery SET stack_id=$1, updated_at=now() where id = any($2)")
+            .bind(stack_id)
+            .bind(vec![*gallery_id, candidate.gallery_id])
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(Some(Self {
+            id: stack_id,
+            kind: candidate.kind,
+        }))
+    }
+
+    /// Processed photos of a user stack, the cover first. Empty when the stack
+    /// is not found.
+    pub async fn photos(
+        conn: &crate::DbConn,
+        user_id: &str,
+        stack_id: &Uuid,
+    ) -> QueryResult<Vec<UserPhoto>> {
+        Ok(sqlx::query_as::<_, UserPhoto>(
+            "
+            SELECT g.id, g.created_at, g.thumbnail_path, g.thumbnail_ratio, g.thumbnail_width, g.thumbnail_height, g.blurhash,
+                coalesce(ge.user_img_aria, ge.img_aria) as img_aria, coalesce(ge.user_img_alt, ge.img_alt) as img_alt, coalesce(ge.user_theme, ge.theme) as theme,
+                g.stack_id
+            from photo_stack s
+                join gallery g on g.stack_id = s.id
+                join user_upload u on u.gallery_id=g.id
+                join gallery_rag_embeddings ge on g.embeddings_id = ge.id
+            where s.id=$1 and s.user_id=$2 and u.user_id=$2 and g.archived_at is null
+            order by coalesce(g.id = s.cover_gallery_id, false) desc, g.created_at, g.id
+            ",
+        )
+        .bind(stack_id)
+        .bind(user_id)
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// False when the stack is not found or the photo is not in it.
+    pub async fn set_cover(
+        conn: &crate::DbConn,
+        user_id: &str,
+        stack_id: &Uuid,
+        gallery_id: &Uuid,
+    ) -> QueryResult<bool> {
+        let updated = sqlx::query(
+            "
+            UPDATE photo_stack s SET cover_gallery_id=$3, updated_at=now()
+            where s.id=$1 and s.user_id=$2
+                and exists (SELECT 1 from gallery g where g.id = $3 and g.stack_id = s.id)
+            ",
+        )
+        .bind(stack_id)
+        .bind(user_id)
+        .bind(gallery_id)
+        .execute(conn)
+        .await?;
+        Ok(updated.rows_affected() > 0)
+    }
+}
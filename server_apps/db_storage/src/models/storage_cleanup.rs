@@ -0,0 +1,132 @@
+use derive_getters::Getters;
+use uuid::Uuid;
+
+use crate::errors::QueryResult;
+
+/// Bucket names as stored in `pending_object_delete.bucket`
+pub const FEEDER_BUCKET: &str = "feeder";
+pub const RAGGED_BUCKET: &str = "ragged";
+
+/// Storage object of a deleted photo that still has to be removed from its bucket.
+#[derive(Debug, Clone, Getters, sqlx::FromRow)]
+pub struct PendingObjectDelete {
+    #[getter(copy)]
+    id: i64,
+    /// Logical bucket, `feeder` or `ragged`.
+    bucket: String,
+    path: String,
+    #[getter(copy)]
+    attempts: i32,
+}
+
+impl PendingObjectDelete {
+    /// Removes every db trace of a user photo in a single transaction and queues
+    /// its bucket objects for deletion.
+    /// None when the photo does not exist or belongs to someone else.
+    pub async fn delete_photo(
+        conn: &crate::DbConn,
+        user_id: &str,
+        gallery_id: &Uuid,
+    ) -> QueryResult<Option<Vec<PendingObjectDelete>>> {
+        let mut tx = conn.begin().await?;
+
+        // Ownership check. Nothing is deleted (rollback on drop) when it fails.
+        let owned = sqlx::query("DELETE from user_upload where gallery_id=$1 and user_id=$2")
+            .bind(gallery_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        if owned.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let renditions: Vec<String> =
+            sqlx::query_scalar("DELETE from rendition where gallery_id=$1 returning path")
+                .bind(gallery_id)
+                .fetch_all(&mut *tx)
+                .await?;
+
+        let gallery: Option<(String, Option<String>, Option<i64>)> = sqlx::query_as(
+            "DELETE from gallery where id=$1 returning path, thumbnail_path, embeddings_id",
+        )
+        .bind(gallery_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let (path, thumbnail_path, embeddings_id) = match gallery {
+            Some(g) => g,
+            None => return Ok(None),
+        };
+
+        if let Some(embeddings_id) = embeddings_id {
+            sqlx::query("DELETE from gallery_rag_embeddings where id=$1")
+                .bind(embeddings_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        // The original is moved into ragged once the thumbnail is created.
+        let mut buckets = vec![match thumbnail_path {
+            Some(_) => RAGGED_BUCKET.to_string(),
+            None => FEEDER_BUCKET.to_string(),
+        }];
+        let mut paths = vec![path];
+        if let Some(thumbnail) = thumbnail_path {
+            buckets.push(RAGGED_BUCKET.to_string());
+            paths.push(thumbnail);
+        }
+        // The thumbnail doubles as a rendition.
+        for rendition in renditions.into_iter().filter(|r| !paths.contains(r)) {
+            buckets.push(RAGGED_BUCKET.to_string());
+            paths.push(rendition);
+        }
+
+        let pending = sqlx::query_as::<_, PendingObjectDelete>(
+            "
+            INSERT into pending_object_delete(bucket, path)
+            SELECT * from unnest($1::text[], $2::text[])
+            returning id, bucket, path, attempts
+            ",
+        )
+        .bind(buckets)
+        .bind(paths)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        log::info!("Deleted gallery id={gallery_id}");
+
+        Ok(Some(pending))
+    }
+
+    /// Oldest pending deletes first.
+    pub async fn list_pending(conn: &crate::DbConn, limit: i64) -> QueryResult<Vec<Self>> {
+        Ok(sqlx::query_as::<_, PendingObjectDelete>(
+            "SELECT id, bucket, path, attempts from pending_object_delete order by updated_at limit $1",
+        )
+        .bind(limit)
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// The object is gone from the bucket.
+    pub async fn done(self, conn: &crate::DbConn) -> QueryResult<()> {
+        sqlx::query("DELETE from pending_object_delete where id=$1")
+            .bind(self.id)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Keeps the record for a later retry.
+    pub async fn failed(&mut self, conn: &crate::DbConn, error: &str) -> QueryResult<()> {
+        sqlx::query(
+            "UPDATE pending_object_delete SET attempts=attempts+1, last_error=$2, updated_at=now() where id=$1",
+        )
+        .bind(self.id)
+        .bind(error)
+        .execute(conn)
+        .await?;
+        self.attempts += 1;
+        Ok(())
+    }
+}
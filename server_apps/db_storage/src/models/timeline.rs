@@ -0,0 +1,157 @@
+use derive_getters::Getters;
+use time::Date;
+
+use crate::errors::QueryResult;
+use crate::models::user_photos::UserPhoto;
+
+/// Span of a timeline bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BucketSize {
+    Day,
+    Month,
+    Year,
+}
+
+impl BucketSize {
+    /// `date_trunc` field
+    fn as_field(&self) -> &'static str {
+        match self {
+            BucketSize::Day => "day",
+            BucketSize::Month => "month",
+            BucketSize::Year => "year",
+        }
+    }
+}
+
+/// Photos captured in a day, month or year.
+/// Capture dates are the camera wall clock. Photos without one use the
+/// upload time moved to the user offset.
+#[derive(Debug, Getters, sqlx::FromRow)]
+pub struct TimelineBucket {
+    year: i32,
+    /// 0 on year buckets
+    month: i32,
+    /// 0 on month and year buckets
+    day: i32,
+    count: i64,
+}
+
+impl TimelineBucket {
+    /// Buckets of the user photos, newest first. Empty buckets are left out.
+    /// `year` limits the buckets to a single year.
+    pub async fn list_for_user(
+        conn: &crate::DbConn,
+        user_id: &str,
+        size: BucketSize,
+        utc_offset_minutes: i32,
+        year: Option<i32>,
+    ) -> QueryResult<Vec<Self>> {
+        Ok(sqlx::query_as::<_, TimelineBucket>(
+            "
+            with captured as (
+                SELECT date_trunc($3, coalesce(pm.taken_at, (g.created_at + make_interval(mins => $2)) at time zone 'UTC')) as bucket
+                from gallery g
+                    join user_upload u on u.gallery_id=g.id
+                    join gallery_rag_embeddings ge on g.embeddings_id = ge.id
+                    left join photo_metadata pm on pm.gallery_id = g.id
+                where u.user_id=$1 and g.archived_at is null
+            )
+            SELECT extract(year from bucket)::int4 as year,
+                case when $3 = 'year' then 0 else extract(month from bucket)::int4 end as month,
+                case when $3 = 'day' then extract(day from bucket)::int4 else 0 end as day,
+                count(1) as count
+            from captured
+            where $4::int4 is null or extract(year from bucket) = $4
+            group by bucket
+            order by bucket desc
+            ",
+        )
+        .bind(user_id)
+        .bind(utc_offset_minutes)
+        .bind(size.as_field())
+        .bind(year)
+        .fetch_all(conn)
+        .await?)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct MemoryPhoto {
+    #[sqlx(flatten)]
+    photo: UserPhoto,
+    year: i32,
+}
+
+/// Photos captured on the same day of an earlier year.
+#[derive(Debug, Getters)]
+pub struct Memory {
+    year: i32,
+    photos: Vec<UserPhoto>,
+}
+
+impl Memory {
+    pub fn photos_mut(&mut self) -> &mut Vec<UserPhoto> {
+        &mut self.photos
+    }
+
+    /// "On this day": photos captured on the month and day of `today` in the
+    /// previous years, newest year first. Up to `per_year` photos each year.
+    /// On the 28th of February of a common year the 29th is included.
+    pub async fn on_this_day(
+        conn: &crate::DbConn,
+        user_id: &str,
+        today: Date,
+        utc_offset_minutes: i32,
+        per_year: i64,
+    ) -> QueryResult<Vec<Self>> {
+        let leap_day = today.month() == time::Month::February
+            && today.day() == 28
+            && !time::util::is_leap_year(today.year());
+
+        let photos = sqlx::query_as::<_, MemoryPhoto>(
+            "
+            SELECT id, created_at, thumbnail_path, thumbnail_ratio, thumbnail_width, thumbnail_height, blurhash, img_aria, img_alt, theme, year
+            from (
+                SELECT g.id, g.created_at, g.thumbnail_path, g.thumbnail_ratio, g.thumbnail_width, g.thumbnail_height, g.blurhash,
+                    coalesce(ge.user_img_aria, ge.img_aria) as img_aria, coalesce(ge.user_img_alt, ge.img_alt) as img_alt, coalesce(ge.user_theme, ge.theme) as theme,
+                    extract(year from c.captured_at)::int4 as year,
+                    row_number() over (partition by extract(year from c.captured_at) order by c.captured_at desc, g.id desc) as position
+                from gallery g
+                    join user_upload u on u.gallery_id=g.id
+                    join gallery_rag_embeddings ge on g.embeddings_id = ge.id
+                    left join photo_metadata pm on pm.gallery_id = g.id
+                    cross join lateral (
+                        SELECT coalesce(pm.taken_at, (g.created_at + make_interval(mins => $2)) at time zone 'UTC') as captured_at
+                    ) c
+                where u.user_id=$1 and g.archived_at is null
+                    and extract(year from c.captured_at) < extract(year from $3::date)
+                    and extract(month from c.captured_at) = extract(month from $3::date)
+                    and (extract(day from c.captured_at) = extract(day from $3::date)
+                        or ($4 and extract(day from c.captured_at) = 29))
+            ) memories
+            where position <= $5
+            order by year desc, position
+            ",
+        )
+        .bind(user_id)
+        .bind(utc_offset_minutes)
+        .bind(today)
+        .bind(leap_day)
+        .bind(per_year)
+        .fetch_all(conn)
+        .await?;
+
+        let mut memories: Vec<Memory> = vec![];
+        for MemoryPhoto { photo, year } in photos {
+            match memories.last_mut() {
+                Some(memory) if memory.year == year => memory.photos.push(photo),
+                _ => memories.push(Memory {
+                    year,
+                    photos: vec![photo],
+                }),
+            }
+        }
+
+        Ok(memories)
+    }
+}
@@ -0,0 +1,101 @@
+use derive_getters::Getters;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::errors::QueryResult;
+
+/// Steps of an upload through the feeder, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ProcessingStatus {
+    /// Upload url handed to the user, the object is not in the bucket yet.
+    Pending,
+    Uploaded,
+    ThumbnailReady,
+    Embedded,
+    /// LLM descriptors linked. Processing is complete.
+    Described,
+    /// In the gallery and searchable, but the LLM descriptors are missing.
+    /// The error is kept with the upload.
+    DescriptionFailed,
+    Failed,
+}
+
+impl ProcessingStatus {
+    /// No further updates are expected.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            ProcessingStatus::Described
+                | ProcessingStatus::DescriptionFailed
+                | ProcessingStatus::Failed
+        )
+    }
+}
+
+/// Processing status of a user upload.
+#[derive(Debug, Clone, Getters, sqlx::FromRow)]
+pub struct UploadProgress {
+    upload_id: Uuid,
+    /// Upload name as requested by the user. `feeder/` prefixed on old uploads.
+    filename: String,
+    gallery_id: Option<Uuid>,
+    #[getter(copy)]
+    status: ProcessingStatus,
+    error: Option<String>,
+    updated_at: OffsetDateTime,
+}
+
+impl UploadProgress {
+    /// Records a processing step of the upload.
+    pub async fn set(
+        conn: &crate::DbConn,
+        upload_id: &Uuid,
+        status: ProcessingStatus,
+        error: Option<&str>,
+    ) -> QueryResult<()> {
+        sqlx::query(
+            "
+            UPDATE user_upload SET processing_status=$2, processing_error=$3, processing_updated_at=now()
+            where id=$1
+            ",
+        )
+        .bind(upload_id)
+        .bind(status)
+        .bind(error)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Uploads of the user updated after `since`, oldest change first.
+    /// Without `since` it returns the current state: the given uploads, or
+    /// every unfinished upload when `upload_ids` is empty.
+    pub async fn list_for_user(
+        conn: &crate::DbConn,
+        user_id: &str,
+        upload_ids: &[Uuid],
+        since: Option<OffsetDateTime>,
+    ) -> QueryResult<Vec<Self>> {
+        Ok(sqlx::query_as::<_, UploadProgress>(
+            "
+            SELECT id as upload_id, coalesce(original_filename, filename) as filename, gallery_id, processing_status as status,
+                processing_error as error, processing_updated_at as updated_at
+            from user_upload
+            where user_id=$1
+                and (cardinality($2::uuid[]) = 0 or id = any($2))
+                and case
+                    when $3::timestamptz is not null then processing_updated_at > $3
+                    when cardinality($2::uuid[]) = 0 then processing_status not in ('described', 'description_failed', 'failed')
+                    else true
+                end
+            order by processing_updated_at, id
+            ",
+        )
+        .bind(user_id)
+        .bind(upload_ids)
+        .bind(since)
+        .fetch_all(conn)
+        .await?)
+    }
+}
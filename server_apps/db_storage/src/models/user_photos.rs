@@ -1,98 +1,814 @@
+use std::str::FromStr;
+
 use derive_getters::Getters;
+use pgvector::Vector;
+use time::OffsetDateTime;
+use uuid::Uuid;
 
 use crate::errors::QueryResult;
+use crate::models::palette::{ColorFilter, NAMED_COLOR_MIN_SHARE, NEAR_COLOR_MIN_SHARE};
+use crate::models::renditions::Rendition;
+use crate::models::{GalleryEmbeddings, NearestQuery};
 
-#[derive(Debug, Getters)]
+#[derive(Debug, Getters, sqlx::FromRow)]
 pub struct UserPhoto {
+    id: Uuid,
+    created_at: OffsetDateTime,
+    thumbnail_path: Option<String>,
+    thumbnail_ratio: Option<String>,
+    thumbnail_width: Option<i32>,
+    thumbnail_height: Option<i32>,
+    /// Placeholder drawn until the thumbnail loads.
+    blurhash: Option<String>,
+
+    theme: Option<String>,
+    img_alt: Option<String>,
+    img_aria: Option<String>,
+    /// Not loaded by the queries, see `Rendition::list_for_galleries`.
+    #[sqlx(skip)]
+    renditions: Vec<Rendition>,
+    /// Set when the photo is part of a RAW+JPEG or burst stack.
+    #[sqlx(default)]
+    stack_id: Option<Uuid>,
+    /// Photos of the stack, only loaded by the gallery listing. 0 otherwise.
+    #[sqlx(default)]
+    stack_size: i64,
+}
+
+/// Filters a user may apply over its gallery.
+/// `None` means the filter is not applied.
+#[derive(Debug, Default, Clone)]
+pub struct PhotoFilter {
+    pub theme: Option<String>,
+    pub ratio: Option<String>,
+    /// Only the photos in this album.
+    pub album: Option<Uuid>,
+    /// Lists the archived photos instead of the gallery.
+    pub archived: bool,
+    /// Photos tagged with this keyword.
+    pub keyword: Option<String>,
+    /// Upload year, UTC.
+    pub year: Option<i32>,
+    /// Upload month, 1 to 12, of any year unless `year` is set.
+    pub month: Option<i32>,
+    /// Photos with a colour family or close to a colour.
+    pub color: Option<ColorFilter>,
+}
+
+/// Keyset pagination position. Pages are ordered by newest first,
+/// `id` breaks the ties of photos created at the same instant.
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct PhotoCursor {
+    created_at: OffsetDateTime,
+    id: Uuid,
+}
+
+impl PhotoCursor {
+    pub fn new(created_at: OffsetDateTime, id: Uuid) -> Self {
+        Self { created_at, id }
+    }
+}
+
+impl From<&UserPhoto> for PhotoCursor {
+    fn from(value: &UserPhoto) -> Self {
+        Self::new(value.created_at, value.id)
+    }
+}
+
+/// Opaque token shared with the clients. `<unix nanos>_<uuid>`
+impl std::fmt::Display for PhotoCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}_{}",
+            self.created_at.unix_timestamp_nanos(),
+            self.id.simple()
+        )
+    }
+}
+
+impl FromStr for PhotoCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (nanos, id) = s.split_once('_').ok_or("Malformed cursor")?;
+        let nanos = nanos.parse::<i128>().map_err(|_| "Malformed cursor time")?;
+        let created_at = OffsetDateTime::from_unix_timestamp_nanos(nanos)
+            .map_err(|_| "Malformed cursor time")?;
+        let id = Uuid::parse_str(id).map_err(|_| "Malformed cursor id")?;
+
+        Ok(Self::new(created_at, id))
+    }
+}
+
+/// A single page of the user gallery.
+#[derive(Debug, Getters)]
+pub struct PhotoPage {
+    photos: Vec<UserPhoto>,
+    /// Position to continue from. None when this is the last page.
+    next: Option<PhotoCursor>,
+}
+
+impl PhotoPage {
+    pub fn photos_mut(&mut self) -> &mut Vec<UserPhoto> {
+        &mut self.photos
+    }
+
+    pub fn into_parts(self) -> (Vec<UserPhoto>, Option<PhotoCursor>) {
+        (self.photos, self.next)
+    }
+}
+
+/// Photo ranked against a query vector.
+#[derive(Debug, Getters, sqlx::FromRow)]
+pub struct ScoredPhoto {
+    #[sqlx(flatten)]
+    photo: UserPhoto,
+    /// Cosine distance to the query, 0 is identical.
+    distance: f64,
+}
+
+impl ScoredPhoto {
+    pub fn photo_mut(&mut self) -> &mut UserPhoto {
+        &mut self.photo
+    }
+
+    /// Cosine similarity, higher is closer.
+    pub fn similarity(&self) -> f64 {
+        1.0 - self.distance
+    }
+}
+
+/// Everything known of a single photo.
+#[derive(Debug, Getters, sqlx::FromRow)]
+pub struct PhotoDetail {
+    id: Uuid,
+    /// Original image bucket path
+    path: String,
     thumbnail_path: Option<String>,
     thumbnail_ratio: Option<String>,
+    thumbnail_width: Option<i32>,
+    thumbnail_height: Option<i32>,
+    original_width: Option<i32>,
+    original_height: Option<i32>,
 
+    description: Option<String>,
+    keywords: Option<Vec<String>>,
     theme: Option<String>,
     img_alt: Option<String>,
     img_aria: Option<String>,
+
+    /// True when the user replaced the AI generated value.
+    description_edited: bool,
+    keywords_edited: bool,
+    theme_edited: bool,
+    img_alt_edited: bool,
+    img_aria_edited: bool,
+
+    /// Upload name as requested by the user. `feeder/` prefixed on old uploads.
+    filename: String,
+    filesize: i32,
+    uploaded_at: OffsetDateTime,
+    created_at: OffsetDateTime,
+    updated_at: OffsetDateTime,
+}
+
+impl PhotoDetail {
+    /// None when the photo does not exist or belongs to someone else.
+    pub async fn get_for_user(
+        conn: &crate::DbConn,
+        user_id: &str,
+        gallery_id: &Uuid,
+    ) -> QueryResult<Option<Self>> {
+        Ok(sqlx::query_as::<_, PhotoDetail>(
+            "
+            SELECT g.id, g.path, g.thumbnail_path, g.thumbnail_ratio, g.thumbnail_width, g.thumbnail_height,
+                g.original_width, g.original_height,
+                coalesce(ge.user_description, ge.description) as description,
+                coalesce(ge.user_keywords, ge.keywords) as keywords,
+                coalesce(ge.user_theme, ge.theme) as theme,
+                coalesce(ge.user_img_alt, ge.img_alt) as img_alt,
+                coalesce(ge.user_img_aria, ge.img_aria) as img_aria,
+                coalesce(ge.user_description is not null, false) as description_edited,
+                coalesce(ge.user_keywords is not null, false) as keywords_edited,
+                coalesce(ge.user_theme is not null, false) as theme_edited,
+                coalesce(ge.user_img_alt is not null, false) as img_alt_edited,
+                coalesce(ge.user_img_aria is not null, false) as img_aria_edited,
+                coalesce(u.original_filename, u.filename) as filename, u.filesize, u.created_at as uploaded_at, g.created_at, g.updated_at
+            from gallery g 
+                join user_upload u on u.gallery_id=g.id 
+                left join gallery_rag_embeddings ge on g.embeddings_id = ge.id 
+            where u.user_id=$1 and g.id=$2
+            ",
+        )
+        .bind(user_id)
+        .bind(gallery_id)
+        .fetch_optional(conn)
+        .await?)
+    }
+
+    /// Upload name without the bucket folder.
+    pub fn download_name(&self) -> &str {
+        self.filename
+            .strip_prefix("feeder/")
+            .unwrap_or(&self.filename)
+    }
+
+    pub fn set_signed_url(&mut self, url: String) {
+        self.thumbnail_path = Some(url);
+    }
+}
+
+/// Search signal that found a photo.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchSignal {
+    Keyword,
+    Vector,
+    Both,
 }
 
-struct Counted {
-    count: Option<i64>,
+/// Photo ranked by the keyword and vector signals together.
+#[derive(Debug, Getters, sqlx::FromRow)]
+pub struct HybridPhoto {
+    #[sqlx(flatten)]
+    photo: UserPhoto,
+    /// Cosine distance to the query, 0 is identical.
+    distance: f64,
+    /// Reciprocal rank fusion score, higher is better.
+    score: f64,
+    #[getter(skip)]
+    vector_match: bool,
+    #[getter(skip)]
+    keyword_match: bool,
 }
 
+impl HybridPhoto {
+    pub fn photo_mut(&mut self) -> &mut UserPhoto {
+        &mut self.photo
+    }
+
+    /// Cosine similarity, higher is closer.
+    pub fn similarity(&self) -> f64 {
+        1.0 - self.distance
+    }
+
+    pub fn matched_by(&self) -> MatchSignal {
+        match (self.keyword_match, self.vector_match) {
+            (true, true) => MatchSignal::Both,
+            (true, false) => MatchSignal::Keyword,
+            _ => MatchSignal::Vector,
+        }
+    }
+}
+
+/// Reciprocal rank fusion constant. Dampens the weight of the top ranks.
+const RRF_K: i64 = 60;
+/// Tag similarity needed for a typo to match. pg_trgm default (0.6) misses
+/// a swapped letter in short words.
+const TAG_SIMILARITY_THRESHOLD: &str = "0.4";
+
 impl UserPhoto {
-    pub async fn get_photos(conn: &crate::DbConn, user_id: &str) -> QueryResult<Vec<Self>> {
-        Ok(sqlx::query_as!(
-            UserPhoto,
+    /// Returns up to `size` photos placed after the `after` cursor.
+    /// Fetches one extra row to know if there is a next page.
+    /// A stack is listed once, by its cover or the first of its photos that
+    /// match the filter. The filtered rows are not materialized, the cursor
+    /// and the stack check run on them so the page reads only what it needs.
+    pub async fn get_photos(
+        conn: &crate::DbConn,
+        user_id: &str,
+        filter: &PhotoFilter,
+        after: Option<&PhotoCursor>,
+        size: i64,
+    ) -> QueryResult<PhotoPage> {
+        let mut photos = sqlx::query_as::<_, UserPhoto>(
+            "
+            with filtered as not materialized (
+                SELECT g.id, g.created_at, g.thumbnail_path, g.thumbnail_ratio, g.thumbnail_width, g.thumbnail_height, g.blurhash,
+                    coalesce(ge.user_img_aria, ge.img_aria) as img_aria, coalesce(ge.user_img_alt, ge.img_alt) as img_alt, coalesce(ge.user_theme, ge.theme) as theme,
+                    g.stack_id, s.cover_gallery_id
+                from gallery g 
+                    join user_upload u on u.gallery_id=g.id 
+                    join gallery_rag_embeddings ge on g.embeddings_id = ge.id 
+                    left join photo_stack s on s.id = g.stack_id
+                where u.user_id=$1
+                    and ($2::text is null or coalesce(ge.user_theme, ge.theme, 'Unthemed') = $2)
+                    and ($3::text is null or g.thumbnail_ratio = $3)
+                    and ($7::uuid is null or exists (
+                        SELECT 1 from album_item ai where ai.gallery_id = g.id and ai.album_id = $7
+                    ))
+                    and (g.archived_at is not null) = $8
+                    and ($9::text is null or $9 = any(coalesce(ge.user_keywords, ge.keywords)))
+                    and ($10::int4 is null or extract(year from u.created_at at time zone 'UTC') = $10)
+                    and ($11::int4 is null or extract(month from u.created_at at time zone 'UTC') = $11)
+                    and ($12::text is null or (
+                        SELECT sum(p.share) from embeddings_palette p where p.embeddings_id = ge.id and p.color_name = $12
+                    ) >= $13::float4)
+                    and ($14::float4[] is null or exists (
+                        SELECT 1 from embeddings_palette p
+                        where p.embeddings_id = ge.id and p.share >= $16::float4
+                            and sqrt(power(p.lab_l - $14[1], 2) + power(p.lab_a - $14[2], 2) + power(p.lab_b - $14[3], 2)) <= $15::float4
+                    ))
+            )
+            SELECT f.id, f.created_at, f.thumbnail_path, f.thumbnail_ratio, f.thumbnail_width, f.thumbnail_height, f.blurhash, f.img_aria, f.img_alt, f.theme, f.stack_id,
+                (SELECT count(1) from gallery m
+                    where m.stack_id = f.stack_id and m.embeddings_id is not null and (m.archived_at is not null) = $8
+                ) as stack_size
+            from filtered f
+            where ($4::timestamptz is null or (f.created_at, f.id) < ($4, $5))
+                and not exists (
+                    SELECT 1 from filtered e
+                    where e.stack_id = f.stack_id and e.id <> f.id
+                        and (e.id = f.cover_gallery_id or (
+                            f.id is distinct from f.cover_gallery_id and (e.created_at, e.id) < (f.created_at, f.id)
+                        ))
+                )
+            order by f.created_at desc, f.id desc
+            limit $6
+            ",
+        )
+        .bind(user_id)
+        .bind(&filter.theme)
+        .bind(&filter.ratio)
+        .bind(after.map(|c| c.created_at))
+        .bind(after.map(|c| c.id))
+        .bind(size + 1)
+        .bind(filter.album)
+        .bind(filter.archived)
+        .bind(&filter.keyword)
+        .bind(filter.year)
+        .bind(filter.month)
+        .bind(filter.color.as_ref().and_then(ColorFilter::name))
+        .bind(NAMED_COLOR_MIN_SHARE)
+        .bind(filter.color.as_ref().and_then(ColorFilter::lab))
+        .bind(filter.color.as_ref().and_then(ColorFilter::tolerance))
+        .bind(NEAR_COLOR_MIN_SHARE)
+        .fetch_all(conn)
+        .await?;
+
+        let next = if photos.len() as i64 > size {
+            photos.truncate(size as usize);
+            photos.last().map(PhotoCursor::from)
+        } else {
+            None
+        };
+
+        Ok(PhotoPage { photos, next })
+    }
+
+    /// Total of gallery entries matching the filter, a stack counts once.
+    /// Ignores the pagination.
+    pub async fn count_photos(
+        conn: &crate::DbConn,
+        user_id: &str,
+        filter: &PhotoFilter,
+    ) -> QueryResult<i64> {
+        let count: (i64,) = sqlx::query_as(
             "
-            SELECT g.thumbnail_path, g.thumbnail_ratio, ge.img_aria, ge.img_alt, ge.theme 
+            SELECT count(distinct coalesce(g.stack_id, g.id))
             from gallery g 
                 join user_upload u on u.gallery_id=g.id 
                 join gallery_rag_embeddings ge on g.embeddings_id = ge.id 
             where u.user_id=$1
+                and ($2::text is null or coalesce(ge.user_theme, ge.theme, 'Unthemed') = $2)
+                and ($3::text is null or g.thumbnail_ratio = $3)
+                and ($4::uuid is null or exists (
+                    SELECT 1 from album_item ai where ai.gallery_id = g.id and ai.album_id = $4
+                ))
+                and (g.archived_at is not null) = $5
+                and ($6::text is null or $6 = any(coalesce(ge.user_keywords, ge.keywords)))
+                and ($7::int4 is null or extract(year from u.created_at at time zone 'UTC') = $7)
+                and ($8::int4 is null or extract(month from u.created_at at time zone 'UTC') = $8)
+                and ($9::text is null or (
+                    SELECT sum(p.share) from embeddings_palette p where p.embeddings_id = ge.id and p.color_name = $9
+                ) >= $10::float4)
+                and ($11::float4[] is null or exists (
+                    SELECT 1 from embeddings_palette p
+                    where p.embeddings_id = ge.id and p.share >= $13::float4
+                        and sqrt(power(p.lab_l - $11[1], 2) + power(p.lab_a - $11[2], 2) + power(p.lab_b - $11[3], 2)) <= $12::float4
+                ))
             ",
-            user_id
         )
+        .bind(user_id)
+        .bind(&filter.theme)
+        .bind(&filter.ratio)
+        .bind(filter.album)
+        .bind(filter.archived)
+        .bind(&filter.keyword)
+        .bind(filter.year)
+        .bind(filter.month)
+        .bind(filter.color.as_ref().and_then(ColorFilter::name))
+        .bind(NAMED_COLOR_MIN_SHARE)
+        .bind(filter.color.as_ref().and_then(ColorFilter::lab))
+        .bind(filter.color.as_ref().and_then(ColorFilter::tolerance))
+        .bind(NEAR_COLOR_MIN_SHARE)
+        .fetch_one(conn)
+        .await?;
+        Ok(count.0)
+    }
+
+    /// User photos closest to the given CLIP vector.
+    /// Same strategy as `GalleryEmbeddings::find_nearest`, the inner query keeps
+    /// the diskann ordering and the gallery columns are joined afterwards.
+    /// Animated images are ranked by their closest sampled frame.
+    pub async fn search_by_embedding(
+        conn: &crate::DbConn,
+        user_id: &str,
+        embedding: Vec<f32>,
+        query: &NearestQuery,
+    ) -> QueryResult<Vec<ScoredPhoto>> {
+        let embed_vec = Vector::from(embedding);
+        let photos = sqlx::query_as::<_, ScoredPhoto>(
+            "
+            SELECT g.id, g.created_at, g.thumbnail_path, g.thumbnail_ratio, g.thumbnail_width, g.thumbnail_height, g.blurhash,
+                coalesce(ge.user_img_aria, ge.img_aria) as img_aria, coalesce(ge.user_img_alt, ge.img_alt) as img_alt, coalesce(ge.user_theme, ge.theme) as theme,
+                nearest.distance
+            from (
+                SELECT hits.id, min(hits.distance) as distance
+                from (
+                    (SELECT ge.id, ge.embedding <=> $2 as distance
+                    from gallery_rag_embeddings ge
+                    where exists (
+                        SELECT 1 from gallery g join user_upload u on u.gallery_id=g.id
+                        where g.embeddings_id = ge.id and u.user_id=$1 and g.archived_at is null
+                    )
+                    order by ge.embedding <=> $2
+                    limit $3)
+                    union all
+                    (SELECT g.embeddings_id, min(fe.embedding <=> $2)
+                    from gallery_frame_embeddings fe
+                        join gallery g on g.id = fe.gallery_id
+                        join user_upload u on u.gallery_id=g.id
+                    where u.user_id=$1 and g.archived_at is null
+                    group by g.embeddings_id
+                    order by 2
+                    limit $3)
+                ) hits
+                group by hits.id
+                order by distance
+                limit $3
+            ) nearest
+                join gallery_rag_embeddings ge on ge.id = nearest.id
+                join gallery g on g.embeddings_id = nearest.id
+                join user_upload u on u.gallery_id=g.id
+            where u.user_id=$1 and g.archived_at is null
+                and ($4::float8 is null or nearest.distance <= $4)
+            order by nearest.distance
+            ",
+        )
+        .bind(user_id)
+        .bind(embed_vec)
+        .bind(query.limit)
+        .bind(query.max_distance)
         .fetch_all(conn)
-        .await?)
+        .await?;
+
+        Ok(photos)
     }
-    pub async fn count_photos(conn: &crate::DbConn, user_id: &str) -> QueryResult<i64> {
-        let count = sqlx::query_as!(Counted, "SELECT count(1) from gallery g join user_upload u on u.gallery_id=g.id where u.user_id=$1", user_id)
-            .fetch_one(conn)
+
+    /// Full text over the LLM descriptors plus trigram over the tags, fused with
+    /// the CLIP ranking by reciprocal rank fusion.
+    /// `query.limit` caps each ranking and the fused output.
+    /// `query.max_distance` only applies to the vector ranking, where animated
+    /// images are ranked by their closest sampled frame.
+    pub async fn hybrid_search(
+        conn: &crate::DbConn,
+        user_id: &str,
+        text: &str,
+        embedding: Vec<f32>,
+        query: &NearestQuery,
+    ) -> QueryResult<Vec<HybridPhoto>> {
+        let embed_vec = Vector::from(embedding);
+        let mut tx = conn.begin().await?;
+        // Local to the transaction, the pool connection is not altered.
+        sqlx::query("SELECT set_config('pg_trgm.word_similarity_threshold', $1, true)")
+            .bind(TAG_SIMILARITY_THRESHOLD)
+            .execute(&mut *tx)
             .await?;
-        Ok(count.count.unwrap_or(0))
+
+        let photos = sqlx::query_as::<_, HybridPhoto>(
+            "
+            with vector_hits as (
+                SELECT nearest.id, row_number() over (order by nearest.distance) as rank
+                from (
+                    SELECT hits.id, min(hits.distance) as distance
+                    from (
+                        (SELECT ge.id, ge.embedding <=> $2 as distance
+                        from gallery_rag_embeddings ge
+                        where exists (
+                            SELECT 1 from gallery g join user_upload u on u.gallery_id=g.id
+                            where g.embeddings_id = ge.id and u.user_id=$1 and g.archived_at is null
+                        )
+                        order by ge.embedding <=> $2
+                        limit $4)
+                        union all
+                        (SELECT g.embeddings_id, min(fe.embedding <=> $2)
+                        from gallery_frame_embeddings fe
+                            join gallery g on g.id = fe.gallery_id
+                            join user_upload u on u.gallery_id=g.id
+                        where u.user_id=$1 and g.archived_at is null
+                        group by g.embeddings_id
+                        order by 2
+                        limit $4)
+                    ) hits
+                    group by hits.id
+                    order by distance
+                    limit $4
+                ) nearest
+                where $5::float8 is null or nearest.distance <= $5
+            ),
+            keyword_hits as (
+                SELECT ge.id, row_number() over (
+                    order by ts_rank_cd(ge.search_document, q.query)
+                        + word_similarity($3, gallery_keywords_text(coalesce(ge.user_keywords, ge.keywords))) desc
+                ) as rank
+                from gallery_rag_embeddings ge, websearch_to_tsquery('english', $3) q(query)
+                where (ge.search_document @@ q.query or $3 <% gallery_keywords_text(coalesce(ge.user_keywords, ge.keywords)))
+                    and exists (
+                        SELECT 1 from gallery g join user_upload u on u.gallery_id=g.id
+                        where g.embeddings_id = ge.id and u.user_id=$1 and g.archived_at is null
+                    )
+                order by rank
+                limit $4
+            ),
+            fused as (
+                SELECT coalesce(v.id, k.id) as embeddings_id,
+                    (coalesce(1.0 / ($6 + v.rank), 0) + coalesce(1.0 / ($6 + k.rank), 0))::float8 as score,
+                    v.id is not null as vector_match,
+                    k.id is not null as keyword_match
+                from vector_hits v full outer join keyword_hits k on v.id = k.id
+            )
+            SELECT g.id, g.created_at, g.thumbnail_path, g.thumbnail_ratio, g.thumbnail_width, g.thumbnail_height, g.blurhash,
+                coalesce(ge.user_img_aria, ge.img_aria) as img_aria, coalesce(ge.user_img_alt, ge.img_alt) as img_alt, coalesce(ge.user_theme, ge.theme) as theme,
+                least(ge.embedding <=> $2, (
+                    SELECT min(fe.embedding <=> $2) from gallery_frame_embeddings fe where fe.gallery_id = g.id
+                )) as distance,
+                f.score, f.vector_match, f.keyword_match
+            from fused f
+                join gallery_rag_embeddings ge on ge.id = f.embeddings_id
+                join gallery g on g.embeddings_id = ge.id
+                join user_upload u on u.gallery_id=g.id
+            where u.user_id=$1 and g.archived_at is null
+            order by f.score desc, distance
+            limit $4
+            ",
+        )
+        .bind(user_id)
+        .bind(embed_vec)
+        .bind(text)
+        .bind(query.limit)
+        .bind(query.max_distance)
+        .bind(RRF_K)
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(photos)
+    }
+
+    /// User photos that look like the given gallery item, the item excluded.
+    /// None when the item does not belong to the user or has no embeddings yet.
+    pub async fn find_similar(
+        conn: &crate::DbConn,
+        user_id: &str,
+        gallery_id: &Uuid,
+        query: &NearestQuery,
+    ) -> QueryResult<Option<Vec<ScoredPhoto>>> {
+        let source = match GalleryEmbeddings::get_for_gallery(conn, user_id, gallery_id).await? {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        // The item itself is always the closest result.
+        let widened = NearestQuery {
+            limit: query.limit + 1,
+            ..*query
+        };
+        let mut similar =
+            Self::search_by_embedding(conn, user_id, source.embedding().clone(), &widened).await?;
+        similar.retain(|s| s.photo.id != *gallery_id);
+        similar.truncate(query.limit as usize);
+
+        Ok(Some(similar))
     }
 
     pub fn set_signed_url(&mut self, url: String) {
         self.thumbnail_path = Some(url);
     }
+
+    pub fn set_renditions(&mut self, renditions: Vec<Rendition>) {
+        self.renditions = renditions;
+    }
+}
+
+/// Number of photos with a facet value.
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct FacetCount {
+    value: String,
+    count: i64,
 }
 
+/// Number of photos uploaded in a month, UTC.
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct MonthCount {
+    year: i32,
+    month: i32,
+    count: i64,
+}
+
+/// Facets of the user gallery, most used value first.
+/// Each facet counts the photos matching the other filters applied, its own
+/// filter is left out so the alternatives stay visible.
 #[derive(Debug, Getters)]
 pub struct FilterableProperties {
-    aspects: Vec<String>,
-    themes: Vec<String>,
+    aspects: Vec<FacetCount>,
+    themes: Vec<FacetCount>,
+    /// Only the most used ones
+    keywords: Vec<FacetCount>,
+    /// Newest first
+    months: Vec<MonthCount>,
+    /// Colour families covering at least `NAMED_COLOR_MIN_SHARE` of the photos.
+    colors: Vec<FacetCount>,
 }
 
+#[derive(sqlx::FromRow)]
 struct FilterableProperty {
-    ratio: Option<String>,
-    theme: Option<String>,
+    facet: String,
+    value: Option<String>,
+    year: Option<i32>,
+    month: Option<i32>,
+    count: i64,
 }
 
 impl From<Vec<FilterableProperty>> for FilterableProperties {
     fn from(value: Vec<FilterableProperty>) -> Self {
-        FilterableProperties {
-            aspects: value
-                .iter()
-                .as_ref()
-                .iter()
-                .filter(|x| x.ratio.is_some())
-                .map(|x| x.ratio.clone().unwrap())
-                .fold(vec![], |mut acc, ratio| {
-                    if acc.contains(&ratio) {
-                        acc.push(ratio.clone());
-                    };
-
-                    acc
-                }),
-            themes: value
-                .iter()
-                .as_ref()
-                .iter()
-                .map(|x| x.theme.clone().unwrap_or("Unthemed".into()))
-                .fold(vec![], |mut acc, ratio| {
-                    if acc.contains(&ratio) {
-                        acc.push(ratio.clone());
-                    };
-
-                    acc
-                }),
+        let mut properties = FilterableProperties {
+            aspects: vec![],
+            themes: vec![],
+            keywords: vec![],
+            months: vec![],
+            colors: vec![],
+        };
+
+        // Rows come sorted by the query
+        for row in value {
+            let count = row.count;
+            match (row.facet.as_str(), row.value, row.year, row.month) {
+                ("aspect", Some(value), _, _) => {
+                    properties.aspects.push(FacetCount { value, count })
+                }
+                ("theme", Some(value), _, _) => properties.themes.push(FacetCount { value, count }),
+                ("keyword", Some(value), _, _) => {
+                    properties.keywords.push(FacetCount { value, count })
+                }
+                ("color", Some(value), _, _) => properties.colors.push(FacetCount { value, count }),
+                ("month", _, Some(year), Some(month)) => {
+                    properties.months.push(MonthCount { year, month, count })
+                }
+                _ => (),
+            }
         }
+
+        properties
     }
 }
 
 impl FilterableProperties {
-    pub async fn get_for_user(conn: &crate::DbConn, user_id: String) -> QueryResult<Self> {
-        let filtered = sqlx::query_as!(FilterableProperty, "SELECT  distinct g.thumbnail_ratio as ratio, ge.theme as theme from gallery g join user_upload u on u.gallery_id=g.id join gallery_rag_embeddings ge on g.embeddings_id = ge.id where u.user_id=$1 group by g.thumbnail_ratio, ge.theme", user_id)
-            .fetch_all(conn)
-            .await?;
+    /// Facets of the photos matching `filter`. Keywords are capped at `keyword_limit`.
+    /// A stack counts once, like in `count_photos`.
+    pub async fn get_for_user(
+        conn: &crate::DbConn,
+        user_id: &str,
+        filter: &PhotoFilter,
+        keyword_limit: i64,
+    ) -> QueryResult<Self> {
+        let filtered = sqlx::query_as::<_, FilterableProperty>(
+            "
+            with photos as (
+                SELECT g.id, coalesce(g.stack_id, g.id) as entry, ge.id as embeddings_id, g.thumbnail_ratio as ratio,
+                    coalesce(ge.user_theme, ge.theme, 'Unthemed') as theme,
+                    coalesce(ge.user_keywords, ge.keywords) as keywords,
+                    extract(year from u.created_at at time zone 'UTC')::int4 as year,
+                    extract(month from u.created_at at time zone 'UTC')::int4 as month,
+                    ($2::text is null or coalesce(ge.user_theme, ge.theme, 'Unthemed') = $2) as theme_match,
+                    ($3::text is null or g.thumbnail_ratio = $3) as ratio_match,
+                    ($5::text is null or $5 = any(coalesce(ge.user_keywords, ge.keywords))) is true as keyword_match,
+                    ($6::int4 is null or extract(year from u.created_at at time zone 'UTC') = $6)
+                        and ($7::int4 is null or extract(month from u.created_at at time zone 'UTC') = $7) as month_match,
+                    ($10::text is null or (
+                        SELECT sum(p.share) from embeddings_palette p where p.embeddings_id = ge.id and p.color_name = $10
+                    ) >= $11::float4) is true as color_match
+                from gallery g
+                    join user_upload u on u.gallery_id=g.id
+                    join gallery_rag_embeddings ge on g.embeddings_id = ge.id
+                where u.user_id=$1
+                    and ($4::uuid is null or exists (
+                        SELECT 1 from album_item ai where ai.gallery_id = g.id and ai.album_id = $4
+                    ))
+                    and (g.archived_at is not null) = $8
+                    and ($12::float4[] is null or exists (
+                        SELECT 1 from embeddings_palette p
+                        where p.embeddings_id = ge.id and p.share >= $14::float4
+                            and sqrt(power(p.lab_l - $12[1], 2) + power(p.lab_a - $12[2], 2) + power(p.lab_b - $12[3], 2)) <= $13::float4
+                    ))
+            )
+            SELECT 'aspect' as facet, ratio as value, null::int4 as year, null::int4 as month, count(distinct entry) as count
+            from photos where ratio is not null and theme_match and keyword_match and month_match and color_match
+            group by ratio
+            union all
+            SELECT 'theme', theme, null, null, count(distinct entry)
+            from photos where ratio_match and keyword_match and month_match and color_match
+            group by theme
+            union all
+            (
+                SELECT 'keyword', keyword, null, null, count(distinct entry)
+                from photos, unnest(keywords) keyword
+                where theme_match and ratio_match and month_match and color_match
+                group by keyword
+                order by count(distinct entry) desc, keyword
+                limit $9
+            )
+            union all
+            SELECT 'month', null, year, month, count(distinct entry)
+            from photos where theme_match and ratio_match and keyword_match and color_match
+            group by year, month
+            union all
+            SELECT 'color', color_name, null, null, count(distinct entry)
+            from (
+                SELECT ph.id, ph.entry, p.color_name
+                from photos ph join embeddings_palette p on p.embeddings_id = ph.embeddings_id
+                where theme_match and ratio_match and keyword_match and month_match
+                group by ph.id, ph.entry, p.color_name
+                having sum(p.share) >= $11::float4
+            ) colors
+            group by color_name
+            order by year desc nulls last, month desc, count desc, value
+            ",
+        )
+        .bind(user_id)
+        .bind(&filter.theme)
+        .bind(&filter.ratio)
+        .bind(filter.album)
+        .bind(&filter.keyword)
+        .bind(filter.year)
+        .bind(filter.month)
+        .bind(filter.archived)
+        .bind(keyword_limit)
+        .bind(filter.color.as_ref().and_then(ColorFilter::name))
+        .bind(NAMED_COLOR_MIN_SHARE)
+        .bind(filter.color.as_ref().and_then(ColorFilter::lab))
+        .bind(filter.color.as_ref().and_then(ColorFilter::tolerance))
+        .bind(NEAR_COLOR_MIN_SHARE)
+        .fetch_all(conn)
+        .await?;
 
         Ok(filtered.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_roundtrips_cursor() {
+        let cursor = PhotoCursor::new(
+            OffsetDateTime::from_unix_timestamp_nanos(1_762_000_000_123_456_000).unwrap(),
+            Uuid::new_v4(),
+        );
+
+        let token = cursor.to_string();
+        let parsed: PhotoCursor = token.parse().unwrap();
+
+        assert_eq!(cursor, parsed);
+        assert!("not-a-cursor".parse::<PhotoCursor>().is_err());
+    }
+
+    #[test]
+    fn it_splits_facet_rows() {
+        let row = |facet: &str, value: Option<&str>, month: Option<(i32, i32)>, count: i64| {
+            FilterableProperty {
+                facet: facet.into(),
+                value: value.map(Into::into),
+                year: month.map(|m| m.0),
+                month: month.map(|m| m.1),
+                count,
+            }
+        };
+        let properties: FilterableProperties = vec![
+            row("month", None, Some((2025, 11)), 3),
+            row("aspect", Some("16:9"), None, 2),
+            row("theme", Some("Travel"), None, 2),
+            row("aspect", Some("4:3"), None, 1),
+            row("keyword", Some("beach"), None, 1),
+            row("color", Some("blue"), None, 1),
+        ]
+        .into();
+
+        assert_eq!(properties.aspects().len(), 2);
+        assert_eq!(properties.aspects()[1].value(), "4:3");
+        assert_eq!(*properties.themes()[0].count(), 2);
+        assert_eq!(properties.keywords()[0].value(), "beach");
+        assert_eq!(properties.months()[0].month(), &11);
+        assert_eq!(properties.colors()[0].value(), "blue");
+    }
+}
@@ -0,0 +1,294 @@
+use std::io::Cursor;
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, Frames, GenericImageView, ImageDecoder, ImageFormat};
+
+use crate::errors::ImageProcessError;
+use crate::image_operations::{THUMBNAIL_MAX_EDGE, encode, resize_longest_edge};
+
+/// Frames decoded at most, longer animations are cut.
+const MAX_FRAMES: usize = 300;
+/// Canvas pixels decoded at most over all the frames. Every frame is
+/// composited on a full canvas, large animations keep fewer frames.
+const MAX_DECODED_PIXELS: u64 = 400_000_000;
+/// Frames embedded with CLIP, evenly spread over the animation.
+pub const SAMPLED_FRAMES: usize = 8;
+/// Browsers play 0 delays at this speed.
+const DEFAULT_DELAY_MS: u32 = 100;
+
+/// Frame of an animated image, composited over the previous ones.
+/// Kept at the thumbnail size at most, the CLIP samples and the preview do
+/// not need more.
+pub struct Frame {
+    pub image: DynamicImage,
+    pub delay_ms: u32,
+}
+
+fn into_error(e: image::ImageError) -> ImageProcessError {
+    log::error!("{e:?}");
+    ImageProcessError::ImageLoad
+}
+
+/// Frame iterator and canvas size of an animated GIF or WebP. None for any
+/// other image.
+fn frames_of(bytes: &[u8]) -> Result<Option<(Frames<'_>, (u32, u32))>, ImageProcessError> {
+    match image::guess_format(bytes) {
+        Ok(ImageFormat::Gif) => {
+            let decoder = GifDecoder::new(Cursor::new(bytes)).map_err(into_error)?;
+            let canvas = decoder.dimensions();
+            Ok(Some((decoder.into_frames(), canvas)))
+        }
+        Ok(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes)).map_err(into_error)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            let canvas = decoder.dimensions();
+            Ok(Some((decoder.into_frames(), canvas)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Frames of an animated GIF or WebP, downscaled as they are decoded. None
+/// for any other image, single frame GIFs included.
+pub fn decode_animation(bytes: &[u8]) -> Result<Option<Vec<Frame>>, ImageProcessError> {
+    let Some((frames, (width, height))) = frames_of(bytes)? else {
+        return Ok(None);
+    };
+    let canvas_pixels = (width as u64 * height as u64).max(1);
+    let max_frames = MAX_FRAMES.min((MAX_DECODED_PIXELS / canvas_pixels) as usize);
+
+    let frames = frames
+        .take(max_frames)
+        .map(|frame| {
+            frame.map(|frame| {
+                let (numerator, denominator) = frame.delay().numer_denom_ms();
+                let delay_ms = match numerator / denominator.max(1) {
+                    0 => DEFAULT_DELAY_MS,
+                    delay => delay,
+                };
+                let mut image = DynamicImage::ImageRgba8(frame.into_buffer());
+                if width.max(height) > THUMBNAIL_MAX_EDGE {
+                    image = image.thumbnail(THUMBNAIL_MAX_EDGE, THUMBNAIL_MAX_EDGE);
+                }
+                Frame { image, delay_ms }
+            })
+        })
+        .collect::<Result<Vec<Frame>, _>>()
+        .map_err(into_error)?;
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+    log::info!("Animation with {} frames of {width}x{height}", frames.len());
+
+    Ok(Some(frames))
+}
+
+/// Frame `index` of the animation at its full size. Only one canvas is kept
+/// while the frames before it are composited.
+pub fn decode_frame(bytes: &[u8], index: usize) -> Result<DynamicImage, ImageProcessError> {
+    let Some((mut frames, _)) = frames_of(bytes)? else {
+        log::error!("Not an animation");
+        return Err(ImageProcessError::ImageLoad);
+    };
+    match frames.nth(index) {
+        Some(frame) => Ok(DynamicImage::ImageRgba8(
+            frame.map_err(into_error)?.into_buffer(),
+        )),
+        None => {
+            log::error!("The animation has no frame {index}");
+            Err(ImageProcessError::ImageLoad)
+        }
+    }
+}
+
+/// Up to `count` frame indices evenly spread, the first frame included.
+pub fn sample_indices(len: usize, count: usize) -> Vec<usize> {
+    if len <= count {
+        return (0..len).collect();
+    }
+    (0..count).map(|i| i * len / count).collect()
+}
+
+/// Position of the embedding closest to the mean of all of them, the frame
+/// that best represents the animation.
+pub fn representative(embeddings: &[Vec<f32>]) -> usize {
+    let Some(first) = embeddings.first() else {
+        return 0;
+    };
+    let mut mean = vec![0f32; first.len()];
+    for embedding in embeddings {
+        for (m, v) in mean.iter_mut().zip(embedding) {
+            *m += v;
+        }
+    }
+
+    let cosine = |a: &[f32], b: &[f32]| {
+        let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        dot / (norm(a) * norm(b)).max(f32::EPSILON)
+    };
+    embeddings
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| cosine(a, &mean).total_cmp(&cosine(b, &mean)))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+/// Lossless animated WebP of the frames, longest edge capped at `max_edge`.
+/// Long animations keep `max_frames` evenly spread frames, each one lasting
+/// as long as the frames it replaces.
+/// The image encoder only writes still images, their VP8L bitstreams are
+/// wrapped into the animation chunks.
+pub fn encode_animated_webp(
+    frames: &[Frame],
+    max_edge: u32,
+    max_frames: usize,
+) -> Result<Vec<u8>, ImageProcessError> {
+    let kept = sample_indices(frames.len(), max_frames);
+
+    let mut canvas = (1, 1);
+    let mut animation = vec![];
+    for (position, index) in kept.iter().enumerate() {
+        let until = kept.get(position + 1).copied().unwrap_or(frames.len());
+        let duration: u32 = frames[*index..until].iter().map(|f| f.delay_ms).sum();
+
+        let resized = resize_longest_edge(&frames[*index].image, max_edge);
+        canvas = resized.image().dimensions();
+        let still = encode(resized.image(), ImageFormat::WebP)?;
+        let bitstream = riff_chunks(&still)
+            .into_iter()
+            .find(|(fourcc, _)| fourcc == b"VP8L")
+            .ok_or_else(|| {
+                log::error!("The WebP encoder did not write a VP8L chunk");
+                ImageProcessError::ImageEncode
+            })?;
+
+        let mut frame = vec![];
+        frame.extend(u24(0)); // X offset
+        frame.extend(u24(0)); // Y offset
+        frame.extend(u24(canvas.0 - 1));
+        frame.extend(u24(canvas.1 - 1));
+        frame.extend(u24(duration.min(0xFF_FFFF)));
+        // Do not blend, keep the previous frame
+        frame.push(0b10);
+        push_chunk(&mut frame, b"VP8L", bitstream.1);
+        push_chunk(&mut animation, b"ANMF", &frame);
+    }
+
+    let mut header = vec![];
+    // Alpha and animation flags
+    header.push(0b0001_0010);
+    header.extend([0, 0, 0]);
+    header.extend(u24(canvas.0 - 1));
+    header.extend(u24(canvas.1 - 1));
+
+    let mut webp = b"WEBP".to_vec();
+    push_chunk(&mut webp, b"VP8X", &header);
+    // Transparent background, loops forever
+    push_chunk(&mut webp, b"ANIM", &[0, 0, 0, 0, 0, 0]);
+    webp.extend(animation);
+
+    let mut riff = vec![];
+    push_chunk(&mut riff, b"RIFF", &webp);
+    Ok(riff)
+}
+
+fn u24(value: u32) -> [u8; 3] {
+    let [a, b, c, _] = value.to_le_bytes();
+    [a, b, c]
+}
+
+/// Chunks are padded to an even size.
+fn push_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend(fourcc);
+    out.extend((payload.len() as u32).to_le_bytes());
+    out.extend(payload);
+    if !payload.len().is_multiple_of(2) {
+        out.push(0);
+    }
+}
+
+/// Chunks inside the RIFF WEBP container.
+fn riff_chunks(webp: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = vec![];
+    let mut offset = 12;
+    while let Some(header) = webp.get(offset..offset + 8) {
+        let fourcc: [u8; 4] = header[0..4].try_into().unwrap_or_default();
+        let size = u32::from_le_bytes(header[4..8].try_into().unwrap_or_default()) as usize;
+        let Some(payload) = webp.get(offset + 8..offset + 8 + size) else {
+            break;
+        };
+        chunks.push((fourcc, payload));
+        offset += 8 + size + size % 2;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn it_samples_evenly() {
+        assert_eq!(sample_indices(3, 8), vec![0, 1, 2]);
+        assert_eq!(sample_indices(100, 4), vec![0, 25, 50, 75]);
+    }
+
+    #[test]
+    fn it_picks_the_frame_closest_to_the_mean() {
+        let embeddings = vec![
+            vec![1.0, 0.0],
+            vec![0.7, 0.7],
+            vec![0.0, 1.0],
+            vec![0.6, 0.8],
+        ];
+        assert_eq!(representative(&embeddings), 1);
+    }
+
+    #[test]
+    fn it_downscales_the_decoded_frames() {
+        let mut gif = vec![];
+        {
+            let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif);
+            for color in [[255, 0, 0, 255], [0, 0, 255, 255]] {
+                let canvas = RgbaImage::from_pixel(1024, 256, Rgba(color));
+                encoder.encode_frame(image::Frame::new(canvas)).unwrap();
+            }
+        }
+
+        let frames = decode_animation(&gif).unwrap().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].image.dimensions(), (512, 128));
+
+        let poster = decode_frame(&gif, 1).unwrap();
+        assert_eq!(poster.dimensions(), (1024, 256));
+        assert_eq!(poster.to_rgba8().get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn it_encodes_a_decodable_animation() {
+        let frames: Vec<Frame> = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 128]]
+            .into_iter()
+            .map(|color| Frame {
+                image: DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 8, Rgba(color))),
+                delay_ms: 50,
+            })
+            .collect();
+
+        let webp = encode_animated_webp(&frames, 8, 2).unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(&webp)).unwrap();
+        assert!(decoder.has_animation());
+        let decoded: Vec<image::Frame> = decoder.into_frames().collect::<Result<_, _>>().unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].buffer().dimensions(), (8, 4));
+        assert_eq!(decoded[0].delay().numer_denom_ms(), (50, 1));
+        assert_eq!(decoded[1].delay().numer_denom_ms(), (100, 1));
+        assert_eq!(decoded[1].buffer().get_pixel(0, 0), &Rgba([0, 255, 0, 255]));
+    }
+}
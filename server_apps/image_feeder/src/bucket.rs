@@ -229,9 +229,14 @@ pub async fn move_to_ragged(filename: &str) -> Result<String, BucketOperationsEr
     // let destination = str::replace(filename, "feeder/", "feeded/");
 
     let random_name = Uuid::new_v4().to_string();
-    // Find the extension or empty. Assume normal extensions.
-    let extension = filename.split(".").last().unwrap_or("");
-    let destination = format!("feeder/{}.{}", random_name, extension);
+    // Keys of new uploads have no extension, `feeder/<upload id>`.
+    let destination = match std::path::Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+    {
+        Some(extension) => format!("feeder/{}.{}", random_name, extension),
+        None => format!("feeder/{}", random_name),
+    };
     log::info!(
         "Origin bucket: {}, filename: {}. Destination {}.",
         source_bucket,
@@ -276,6 +281,23 @@ pub async fn move_to_ragged(filename: &str) -> Result<String, BucketOperationsEr
     Ok(destination)
 }
 
+/// Removes an upload that will not be processed.
+pub async fn remove_from_feeder(filename: &str) -> Result<(), BucketOperationsError> {
+    let source_bucket = std::env::var("BUCKET_FEEDER_NAME").unwrap_or("rag-upload".to_string());
+    let client = b3_client();
+
+    client
+        .delete_object(&source_bucket, filename)
+        .send()
+        .await
+        .map_err(|e| {
+            log::error!("remove_from_feeder: {e:?}");
+            BucketOperationsError::BlobDelete
+        })?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use minio::s3::types::S3Api;
@@ -15,6 +15,17 @@ pub fn get_img_embeddings(img: DynamicImage) -> Result<Vec<f32>, Box<dyn std::er
     Ok(embedding)
 }
 
+/// Embeddings of several images with a single model load, in the same order.
+pub fn get_imgs_embeddings(
+    imgs: Vec<DynamicImage>,
+) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+    let mut model = ImageEmbedding::try_new(
+        ImageInitOptions::new(ImageEmbeddingModel::ClipVitB32).with_show_download_progress(true),
+    )?;
+
+    Ok(model.embed_images(imgs)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
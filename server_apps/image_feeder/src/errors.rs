@@ -10,6 +10,10 @@ pub enum ImageProcessError {
     ImageLoad,
     #[error("Failed to detect format.")]
     ImageGuessFormat,
+    #[error("Failed to encode image.")]
+    ImageEncode,
+    #[error("Uploaded bytes do not match the declared hash.")]
+    HashMismatch,
 }
 
 #[derive(Error, Debug)]
@@ -22,6 +26,8 @@ pub enum BucketOperationsError {
     BlobRead,
     #[error("Failed to Move object.")]
     BlobMove,
+    #[error("Failed to delete object.")]
+    BlobDelete,
 }
 
 #[derive(Error, Debug)]
@@ -45,3 +51,11 @@ pub enum LlmRetrievalError {
     #[error("Failed to setup custom retrieval model.")]
     MultimodalSetup,
 }
+
+#[derive(Error, Debug)]
+pub enum GeocodingError {
+    #[error("Failed to read the places dataset.")]
+    Read,
+    #[error("Failed to parse the places dataset.")]
+    Parse,
+}
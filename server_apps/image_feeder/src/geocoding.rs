@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::errors::GeocodingError;
+
+/// Places further away than this are not used to name a photo.
+const MAX_DISTANCE_KM: f64 = 50.0;
+const EARTH_RADIUS_KM: f64 = 6371.0;
+/// Length of a degree of latitude, and of longitude at the equator.
+const KM_PER_DEGREE: f64 = 111.0;
+
+/// Named place of the dataset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Place {
+    pub city: String,
+    pub region: Option<String>,
+    pub country: Option<String>,
+    latitude: f64,
+    longitude: f64,
+}
+
+/// Offline reverse geocoder over the GeoNames dumps.
+/// Places are bucketed by whole degree cells, a lookup only visits the cells
+/// around the coordinates.
+pub struct Geocoder {
+    cells: HashMap<(i32, i32), Vec<Place>>,
+}
+
+impl Geocoder {
+    /// Loads `cities*.txt`. The admin1 codes and country info files are
+    /// optional, without them the regions and countries are left empty.
+    pub fn load(
+        cities_path: &str,
+        admin1_path: Option<&str>,
+        countries_path: Option<&str>,
+    ) -> Result<Self, GeocodingError> {
+        let open = |path: &str| {
+            File::open(path).map(BufReader::new).map_err(|e| {
+                log::error!("Failed to open {path}. {e}");
+                GeocodingError::Read
+            })
+        };
+
+        let regions = match admin1_path {
+            Some(path) => read_names(open(path)?, 0, 1)?,
+            None => HashMap::new(),
+        };
+        let countries = match countries_path {
+            Some(path) => read_names(open(path)?, 0, 4)?,
+            None => HashMap::new(),
+        };
+
+        let geocoder = Self::from_cities(open(cities_path)?, &regions, &countries)?;
+        log::info!("Loaded {} places", geocoder.len());
+
+        Ok(geocoder)
+    }
+
+    /// Reads the tab separated `cities*.txt` rows.
+    /// Name at 1, latitude at 4, longitude at 5, country code at 8, admin1 code at 10.
+    fn from_cities(
+        cities: impl BufRead,
+        regions: &HashMap<String, String>,
+        countries: &HashMap<String, String>,
+    ) -> Result<Self, GeocodingError> {
+        let mut cells: HashMap<(i32, i32), Vec<Place>> = HashMap::new();
+
+        for line in cities.lines() {
+            let line = line.map_err(|_| GeocodingError::Read)?;
+            let columns: Vec<&str> = line.split('\t').collect();
+            if columns.len() < 11 {
+                continue;
+            }
+            let (latitude, longitude) = match (columns[4].parse(), columns[5].parse()) {
+                (Ok(latitude), Ok(longitude)) => (latitude, longitude),
+                _ => return Err(GeocodingError::Parse),
+            };
+            let country_code = columns[8];
+            let place = Place {
+                city: columns[1].to_string(),
+                region: regions
+                    .get(&format!("{country_code}.{}", columns[10]))
+                    .cloned(),
+                country: countries.get(country_code).cloned(),
+                latitude,
+                longitude,
+            };
+            cells
+                .entry(cell(latitude, longitude))
+                .or_default()
+                .push(place);
+        }
+
+        Ok(Self { cells })
+    }
+
+    fn len(&self) -> usize {
+        self.cells.values().map(Vec::len).sum()
+    }
+
+    /// Closest place to the coordinates, None when nothing is near.
+    pub fn nearest(&self, latitude: f64, longitude: f64) -> Option<&Place> {
+        let (lat_cell, lon_cell) = cell(latitude, longitude);
+
+        let mut nearest: Option<(f64, &Place)> = None;
+        for lat in lat_cell - 1..=lat_cell + 1 {
+            // Longitude degrees shrink towards the poles, the row edge closest
+            // to the pole sets how many cells are in reach.
+            let polar_edge = (lat as f64).abs().max((lat as f64 + 1.0).abs());
+            let lon_span = lon_cells(polar_edge);
+            for lon in lon_cell - lon_span..=lon_cell + lon_span {
+                // Wraps around the antimeridian
+                let lon = (lon + 180).rem_euclid(360) - 180;
+                for place in self.cells.get(&(lat, lon)).into_iter().flatten() {
+                    let distance =
+                        haversine_km(latitude, longitude, place.latitude, place.longitude);
+                    if distance <= MAX_DISTANCE_KM && nearest.is_none_or(|(d, _)| distance < d) {
+                        nearest = Some((distance, place));
+                    }
+                }
+            }
+        }
+
+        nearest.map(|(_, place)| place)
+    }
+}
+
+fn cell(latitude: f64, longitude: f64) -> (i32, i32) {
+    (latitude.floor() as i32, longitude.floor() as i32)
+}
+
+/// Longitude cells on each side within `MAX_DISTANCE_KM`, at most the
+/// whole parallel.
+fn lon_cells(latitude: f64) -> i32 {
+    let km_per_degree = KM_PER_DEGREE * latitude.min(90.0).to_radians().cos();
+    (MAX_DISTANCE_KM / km_per_degree).ceil().clamp(1.0, 180.0) as i32
+}
+
+fn haversine_km(lat_a: f64, lon_a: f64, lat_b: f64, lon_b: f64) -> f64 {
+    let (lat_a, lat_b) = (lat_a.to_radians(), lat_b.to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_lon = (lon_b - lon_a).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Code to name map of a tab separated file. `#` lines are comments.
+fn read_names(
+    rows: impl BufRead,
+    code_column: usize,
+    name_column: usize,
+) -> Result<HashMap<String, String>, GeocodingError> {
+    let mut names = HashMap::new();
+    for line in rows.lines() {
+        let line = line.map_err(|_| GeocodingError::Read)?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let columns: Vec<&str> = line.split('\t').collect();
+        if let (Some(code), Some(name)) = (columns.get(code_column), columns.get(name_column)) {
+            names.insert(code.to_string(), name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_the_nearest_place() {
+        let cities = "\
+2988507\tParis\tParis\t\t48.85341\t2.3488\tP\tPPLC\tFR\t\t11\t75\t\t\t2138551\t\t42\tEurope/Paris\t2024-01-01
+2996944\tLyon\tLyon\t\t45.74846\t4.84671\tP\tPPLA\tFR\t\t84\t69\t\t\t522250\t\t170\tEurope/Paris\t2024-01-01
+2729907\tLongyearbyen\tLongyearbyen\t\t78.2232\t15.6267\tP\tPPLA\tSJ\t\t21\t\t\t\t2060\t\t15\tArctic/Longyearbyen\t2024-01-01
+";
+        let regions = read_names(
+            "FR.11\tÎle-de-France\tIle-de-France\t3012874\n".as_bytes(),
+            0,
+            1,
+        )
+        .unwrap();
+        let countries = read_names(
+            "# ISO\tISO3\nFR\tFRA\t250\tFR\tFrance\tParis\n".as_bytes(),
+            0,
+            4,
+        )
+        .unwrap();
+        let geocoder = Geocoder::from_cities(cities.as_bytes(), &regions, &countries).unwrap();
+
+        // Versailles
+        let place = geocoder.nearest(48.8049, 2.1204).unwrap();
+        assert_eq!(place.city, "Paris");
+        assert_eq!(place.region.as_deref(), Some("Île-de-France"));
+        assert_eq!(place.country.as_deref(), Some("France"));
+        assert_eq!(geocoder.nearest(45.76, 4.83).unwrap().city, "Lyon");
+        // Two longitude cells away, a degree is about 23 km up there
+        assert_eq!(geocoder.nearest(78.2, 13.9).unwrap().city, "Longyearbyen");
+        // Atlantic
+        assert!(geocoder.nearest(45.0, -30.0).is_none());
+    }
+}
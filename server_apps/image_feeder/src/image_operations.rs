@@ -1,9 +1,12 @@
+use std::f32::consts::PI;
 use std::io::Cursor;
 
 use crate::errors::ImageProcessError;
+use crate::raw::RawFile;
 use base64::{Engine, engine::general_purpose};
 use derive_getters::Getters;
-use image::{DynamicImage, GenericImageView, ImageFormat};
+use image::{DynamicImage, GenericImageView, ImageDecoder, ImageFormat, ImageReader};
+use sha2::{Digest, Sha256};
 // use reqwest::blocking::get;
 
 fn into_error(
@@ -63,34 +66,204 @@ impl ImageData {
 //     Ok(img)
 // }
 
+/// Uploads declare the SHA-256 of the file, hex encoded.
+pub fn verify_hash(bytes: &[u8], declared: &str) -> Result<(), ImageProcessError> {
+    let digest = hex::encode(Sha256::digest(bytes));
+    if !digest.eq_ignore_ascii_case(declared) {
+        log::warn!("Declared hash {declared}, downloaded {digest}");
+        return Err(ImageProcessError::HashMismatch);
+    }
+
+    Ok(())
+}
+
+/// Longest edge of the thumbnails, also the CLIP input.
+pub const THUMBNAIL_MAX_EDGE: u32 = 512;
+
+/// Decodes the image upright, the EXIF orientation is applied.
+/// Camera RAW files are decoded from their embedded preview.
 pub fn image_from_bytes(bytes: &Vec<u8>) -> Result<DynamicImage, ImageProcessError> {
-    let img = image::load_from_memory(bytes)
+    if let Some(raw) = RawFile::parse(bytes) {
+        let img = raw.preview()?;
+        log::info!("RAW preview dimensions: {:?}", img.dimensions());
+        return Ok(img);
+    }
+
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| into_error(Box::new(e), ImageProcessError::ImageGuessFormat))?
+        .into_decoder()
         .map_err(|e| into_error(Box::new(e), ImageProcessError::ImageLoad))?;
-    log::info!("Image dimensions: {:?}", img.dimensions());
+    let orientation = decoder
+        .orientation()
+        .map_err(|e| into_error(Box::new(e), ImageProcessError::ImageLoad))?;
+    let mut img = DynamicImage::from_decoder(decoder)
+        .map_err(|e| into_error(Box::new(e), ImageProcessError::ImageLoad))?;
+    img.apply_orientation(orientation);
+    log::info!(
+        "Image dimensions: {:?}, orientation {orientation:?}",
+        img.dimensions()
+    );
 
     Ok(img)
 }
 
 /// Webp always
-/// Width capped at 512
+/// Longest edge capped at 512, the aspect ratio is kept. Smaller images are not enlarged.
 pub fn create_thumbnail(img: &DynamicImage) -> ImageData {
-    let (mut width, mut height) = img.dimensions();
+    resize_longest_edge(img, THUMBNAIL_MAX_EDGE)
+}
+
+/// Longest edge capped at `max_edge`, the aspect ratio is kept. Smaller images are not enlarged.
+pub fn resize_longest_edge(img: &DynamicImage, max_edge: u32) -> ImageData {
+    let (width, height) = img.dimensions();
     let aspect_ratio = width as f32 / height as f32;
 
-    width = 512;
-    height = (512_f32 * aspect_ratio) as u32;
-    log::info!("Aspect ratio {width}:{height}");
+    let resized = if width.max(height) > max_edge {
+        img.resize(max_edge, max_edge, image::imageops::FilterType::Lanczos3)
+    } else {
+        img.clone()
+    };
+    let (width, height) = resized.dimensions();
+    log::info!("Resized to {width}x{height}, aspect ratio {aspect_ratio}");
 
-    // Resize to 512x512
-    let resized: DynamicImage = img.resize(width, height, image::imageops::FilterType::Lanczos3);
     ImageData {
         image: resized,
-        height: height,
-        width: width,
-        aspect_ratio: aspect_ratio,
+        height,
+        width,
+        aspect_ratio,
+    }
+}
+
+/// Lossy JPEG quality, photos end up a fraction of their lossless WebP size.
+const JPEG_QUALITY: u8 = 82;
+
+/// Encodes into `format`. WebP is lossless, JPEG drops the alpha channel,
+/// AVIF favours the encoding speed.
+pub fn encode(img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, ImageProcessError> {
+    // The encoders take 8 bit RGB(A) only, JPEG takes RGB.
+    let img = if img.color().has_alpha() && format != ImageFormat::Jpeg {
+        DynamicImage::ImageRgba8(img.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(img.to_rgb8())
+    };
+
+    let mut bytes: Vec<u8> = Vec::new();
+    let written = match format {
+        ImageFormat::Avif => img.write_with_encoder(
+            image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut bytes, 8, 70),
+        ),
+        ImageFormat::Jpeg => img.write_with_encoder(
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY),
+        ),
+        _ => img.write_to(&mut Cursor::new(&mut bytes), format),
+    };
+    written.map_err(|e| into_error(Box::new(e), ImageProcessError::ImageEncode))?;
+
+    Ok(bytes)
+}
+
+const BASE83: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// BlurHash of the image with 4x3 components, 3x4 on portraits.
+/// It is computed over a 32px copy, the placeholder is blurred anyway.
+pub fn blurhash(img: &DynamicImage) -> String {
+    let small = img.thumbnail(32, 32).to_rgb8();
+    let (width, height) = small.dimensions();
+    let (components_x, components_y) = if width >= height { (4, 3) } else { (3, 4) };
+    let linear: Vec<[f32; 3]> = small.pixels().map(|p| p.0.map(srgb_to_linear)).collect();
+
+    let scale = 1.0 / (width * height) as f32;
+    let mut factors: Vec<[f32; 3]> = vec![];
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0f32; 3];
+            for y in 0..height {
+                for x in 0..width {
+                    let basis = normalisation
+                        * (PI * i as f32 * x as f32 / width as f32).cos()
+                        * (PI * j as f32 * y as f32 / height as f32).cos();
+                    let pixel = linear[(y * width + x) as usize];
+                    for (f, p) in factor.iter_mut().zip(pixel) {
+                        *f += basis * p;
+                    }
+                }
+            }
+            factors.push(factor.map(|f| f * scale));
+        }
+    }
+
+    let (dc, ac) = (factors[0], &factors[1..]);
+    let actual_max = ac.iter().flatten().fold(0f32, |max, v| max.max(v.abs()));
+    let quantised_max = (actual_max * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+    let maximum = (quantised_max + 1) as f32 / 166.0;
+    let quantise = |v: f32| {
+        let v = (v / maximum).abs().sqrt().copysign(v);
+        (v * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32
+    };
+
+    let mut hash = String::new();
+    encode83(&mut hash, (components_x - 1) + (components_y - 1) * 9, 1);
+    encode83(&mut hash, quantised_max, 1);
+    let [r, g, b] = dc.map(linear_to_srgb);
+    encode83(&mut hash, (r << 16) + (g << 8) + b, 4);
+    for [r, g, b] in ac {
+        encode83(
+            &mut hash,
+            quantise(*r) * 19 * 19 + quantise(*g) * 19 + quantise(*b),
+            2,
+        );
+    }
+    hash
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    if v <= 0.0031308 {
+        (v * 12.92 * 255.0 + 0.5) as u32
+    } else {
+        ((1.055 * v.powf(1.0 / 2.4) - 0.055) * 255.0 + 0.5) as u32
+    }
+}
+
+fn encode83(hash: &mut String, value: u32, length: u32) {
+    for i in 1..=length {
+        let digit = (value / 83u32.pow(length - i)) % 83;
+        hash.push(BASE83[digit as usize] as char);
     }
 }
 
+/// Difference hash (dHash) of the image.
+/// Each bit tells if a pixel of the 9x8 grayscale image is brighter than its
+/// right neighbour. Resized or re-encoded copies differ by a few bits.
+pub fn perceptual_hash(img: &DynamicImage) -> u64 {
+    let small = img
+        .resize_exact(9, 8, image::imageops::FilterType::Triangle)
+        .to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
 /// Encodes the given Image into a png base 64 image.
 /// It also adds the image type prefix to the output
 /// `data:image/png;base64,`
@@ -109,3 +282,65 @@ pub fn to_llava_base64(img: &DynamicImage) -> String {
     let img_base64 = general_purpose::STANDARD.encode(img_buf);
     img_base64
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_verifies_hash() {
+        let sha256_abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+        assert!(verify_hash(b"abc", sha256_abc).is_ok());
+        assert!(verify_hash(b"abc", &sha256_abc.to_uppercase()).is_ok());
+        assert!(verify_hash(b"abd", sha256_abc).is_err());
+    }
+
+    #[test]
+    fn it_bounds_the_thumbnail_longest_edge() {
+        let landscape = create_thumbnail(&DynamicImage::new_rgb8(2000, 1000));
+        assert_eq!((landscape.width, landscape.height), (512, 256));
+        assert_eq!(landscape.ratio_as_str(), "landscape");
+
+        let tall = create_thumbnail(&DynamicImage::new_rgb8(600, 3000));
+        assert_eq!((tall.width, tall.height), (102, 512));
+        assert_eq!(tall.ratio_as_str(), "tall");
+
+        let small = create_thumbnail(&DynamicImage::new_rgb8(300, 400));
+        assert_eq!((small.width, small.height), (300, 400));
+        assert_eq!(small.ratio_as_str(), "portrait");
+    }
+
+    #[test]
+    fn it_encodes_blurhash_components() {
+        let white = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            64,
+            32,
+            image::Rgb([255, 255, 255]),
+        ));
+        let hash = blurhash(&white);
+        // 4x3 components, average color #FFFFFF
+        assert_eq!(hash.len(), 28);
+        assert_eq!(&hash[0..1], "L");
+        assert_eq!(&hash[2..6], "TSUA");
+
+        let portrait = blurhash(&DynamicImage::new_rgb8(30, 60));
+        assert_eq!(&portrait[0..1], "T");
+        assert_eq!(&portrait[2..6], "0000");
+    }
+
+    #[test]
+    fn it_hashes_resized_copies_alike() {
+        // Darker to the right on every row
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(300, 200, |x, y| {
+            image::Rgb([(255 - x * 255 / 299) as u8, (y * 255 / 199) as u8, 128])
+        }));
+        let resized = img.resize(150, 100, image::imageops::FilterType::Lanczos3);
+        let flipped = img.fliph();
+
+        let hash = perceptual_hash(&img);
+        assert_eq!(hash, u64::MAX);
+        assert!((hash ^ perceptual_hash(&resized)).count_ones() <= 4);
+        assert!((hash ^ perceptual_hash(&flipped)).count_ones() > 32);
+    }
+}
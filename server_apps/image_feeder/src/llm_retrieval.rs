@@ -9,16 +9,21 @@ pub enum ImagePrompt {
     Description,
     Tags,
     SemiStructured,
+    /// `SemiStructured` with the keywords and caption embedded in the file.
+    SemiStructuredWithContext {
+        keywords: Vec<String>,
+        caption: Option<String>,
+    },
 }
 
 impl ImagePrompt {
     pub fn to_prompt(&self) -> String {
-        match self {
+        let prompt = match self {
             ImagePrompt::Description => "What is in this image?",
             ImagePrompt::Tags => {
                 "Please provide a list of tags in the format of comma sepparated values for this image. Make it not more than twenty please the expected format `tag1,tag2,tag3`"
             }
-            ImagePrompt::SemiStructured => "
+            ImagePrompt::SemiStructured | ImagePrompt::SemiStructuredWithContext { .. } => "
                 The following image needs to be described, the output is expected structured. 
                 The fields are as follows.
                 **caption**: a text that can be used as aria-label attribute within a <div> container for img.
@@ -29,7 +34,26 @@ impl ImagePrompt {
                 the output is a json object which structure is the following: 
                 { caption: string, alt: string, theme: string, description: string, tags: string[] }
                 ",
-        }.into()
+        };
+
+        match self {
+            ImagePrompt::SemiStructuredWithContext { keywords, caption } => {
+                let mut prompt = format!(
+                    "{prompt}
+                The author of the image already annotated it, use it as context.
+                Keep the keywords that match the image in the tags.
+                "
+                );
+                if !keywords.is_empty() {
+                    prompt.push_str(&format!("Author keywords: {}\n", keywords.join(", ")));
+                }
+                if let Some(caption) = caption {
+                    prompt.push_str(&format!("Author caption: {caption}\n"));
+                }
+                prompt
+            }
+            _ => prompt.into(),
+        }
     }
 }
 
@@ -1,30 +1,48 @@
 use std::io::Cursor;
 
-use bucket::{download, upload};
+use bucket::{download, remove_from_feeder, upload};
 use db_storage::{
-    db_connect,
-    models::{Gallery, GalleryEmbeddings, NewEmbeddings, NewThumbnail, UserUpload},
+    DbConn, db_connect,
+    models::{
+        Gallery, GalleryEmbeddings, NewEmbeddings, NewThumbnail, UserUpload,
+        frame_embeddings::FrameEmbedding,
+        palette::PaletteColor,
+        photo_metadata::PhotoMetadata,
+        stacks::{BURST_MAX_DISTANCE, BURST_WINDOW_SECONDS, PhotoStack},
+        upload_progress::{ProcessingStatus, UploadProgress},
+    },
 };
-use embeddings::get_img_embeddings;
-use image::DynamicImage;
-use image_operations::{create_thumbnail, image_from_bytes, to_base64, to_llava_base64};
+use animation::{SAMPLED_FRAMES, decode_animation, decode_frame, representative, sample_indices};
+use embeddings::{get_img_embeddings, get_imgs_embeddings};
+use geocoding::Geocoder;
+use image::{DynamicImage, GenericImageView};
+use image_operations::{blurhash, create_thumbnail, image_from_bytes, perceptual_hash, to_base64, to_llava_base64, verify_hash};
 use llm_messages::SemiStructuredMessage;
+use metadata::{merge_tags, read_metadata};
+use palette::dominant_colors;
 use llm_retrieval::{ImagePrompt, fetch_description, fetch_llava_description};
 use queue::{create_consumer, feeder_protocol};
+use renditions::{RenditionSettings, create_animated_preview, create_renditions};
 use simple_logger::SimpleLogger;
 use tokio::sync::mpsc;
 
 use crate::bucket::move_to_ragged;
 
+mod animation;
 mod bucket;
 mod embeddings;
 mod errors;
+mod geocoding;
 mod image_operations;
 // mod llm_llava;
 mod llm_messages;
 mod llm_retrieval;
+mod metadata;
+mod palette;
 mod queue;
 mod queue_messages;
+mod raw;
+mod renditions;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -35,7 +53,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .unwrap();
 
     let (feeder_tx, mut feeder_rx) = mpsc::unbounded_channel();
-    let (genai_tx, mut genai_rx) = mpsc::unbounded_channel::<(DynamicImage, GalleryEmbeddings)>();
+    let (genai_tx, mut genai_rx) = mpsc::unbounded_channel::<(DynamicImage, GalleryEmbeddings, PhotoMetadata, uuid::Uuid)>();
     let pg_url = std::env::var("DATABASE_URL").expect("Missing DATABASE_URL");
     let kafka_url = std::env::var("KAFKA_SERVER_LISTENER").expect("Missing KAFKA_SERVER_LISTENER");
     let kafka_topic = std::env::var("KAFKA_MINIO_TOPIC").expect("Missing KAFKA_MINIO_TOPIC");
@@ -43,6 +61,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let bucket_to_upload = std::env::var("BUCKET_RAGGED_NAME").expect("Missing BUCKET_RAGGED_NAME");
 
+    // Photos are not named after places when the dataset is not configured.
+    let geocoder = match std::env::var("GEONAMES_CITIES_PATH") {
+        Ok(cities) => Some(Geocoder::load(
+            &cities,
+            std::env::var("GEONAMES_ADMIN1_PATH").ok().as_deref(),
+            std::env::var("GEONAMES_COUNTRIES_PATH").ok().as_deref(),
+        )?),
+        Err(_) => {
+            log::warn!("GEONAMES_CITIES_PATH is not set, reverse geocoding is disabled");
+            None
+        }
+    };
+    let rendition_settings = RenditionSettings::from_env();
+
     tokio::spawn(async move {
         let feeder_consumer = match create_consumer(&kafka_url) {
             Ok(f) => f,
@@ -69,73 +101,205 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 };
                 log::info!("msg {:?}", msg);
 
-                let file_bytes = download(&msg.filename).await?;
-
                 // Find the user owner of this image
                 // If the upload record is not found in db. Block the process.
                 let mut user_info = UserUpload::get_by_filename(&db_pool, &msg.filename).await?;
+                set_progress(&db_pool, user_info.id(), ProcessingStatus::Uploaded, None).await;
 
-                let i = image_from_bytes(&file_bytes)?;
-                let thumbnail_512p = create_thumbnail(&i);
-                // Generate embeddings from thumbnail image.
-                let embeddings = get_img_embeddings(thumbnail_512p.image().clone())?;
+                match process_upload(&db_pool, &bucket_to_upload, geocoder.as_ref(), &rendition_settings, &msg.filename, &mut user_info).await {
+                    Ok((img_thumbnail, img_embeddings, img_metadata)) => {
+                        if let Err(e) = genai_tx.send((img_thumbnail, img_embeddings, img_metadata, *user_info.id())){
+                            log::error!("Failed to send thumbnail to genai thread\n{e:?}");
+                        }
+                    },
+                    Err(e) => {
+                        log::error!("Failed to process {}\n{e:?}", msg.filename);
+                        set_progress(&db_pool, user_info.id(), ProcessingStatus::Failed, Some(&e.to_string())).await;
+                    }
+                };
+            },
+            Some(msg) = genai_rx.recv() => {
+                let ( img_thumbnail, img_embeddings, img_metadata, upload_id) = msg;
 
-                // BlobStore thumbnail image.
-                let mut webp_bytes: Vec<u8> = Vec::new();
-                let _ =
-                    thumbnail_512p.image().write_to(&mut Cursor::new(&mut webp_bytes), image::ImageFormat::WebP);
-                let thumbnail_name = format!("thumbnail/{}.webp", uuid::Uuid::new_v4().to_string());
+                match describe(&db_pool, &llm_to_use, &img_thumbnail, &img_embeddings, &img_metadata).await {
+                    Ok(_) => set_progress(&db_pool, &upload_id, ProcessingStatus::Described, None).await,
+                    Err(e) => {
+                        log::error!("Failed to describe upload {upload_id}\n{e:?}");
+                        // The photo is already in the gallery, only its descriptors are missing.
+                        set_progress(&db_pool, &upload_id, ProcessingStatus::DescriptionFailed, Some(&e.to_string())).await;
+                    }
+                };
+            },
+        }
+    }
+}
 
-                let _ = upload(&thumbnail_name, webp_bytes, Some(&bucket_to_upload)).await?;
+/// Records the processing step. A failed write is logged, the feeder keeps
+/// consuming uploads.
+async fn set_progress(db_pool: &DbConn, upload_id: &uuid::Uuid, status: ProcessingStatus, error: Option<&str>) {
+    if let Err(e) = UploadProgress::set(db_pool, upload_id, status, error).await {
+        log::error!("Failed to set upload {upload_id} as {status:?}\n{e:?}");
+    }
+}
 
-                // Create db records
-                let mut img_gallery = Gallery::new(&msg.filename).create(&db_pool).await?;
-                user_info.set_gallery_id(&db_pool, &img_gallery.id()).await?;
+/// Thumbnail, embeddings and db records of a new upload.
+/// Returns what the LLM needs to describe it.
+async fn process_upload(
+    db_pool: &DbConn,
+    bucket_to_upload: &str,
+    geocoder: Option<&Geocoder>,
+    rendition_settings: &RenditionSettings,
+    filename: &str,
+    user_info: &mut UserUpload,
+) -> Result<(DynamicImage, GalleryEmbeddings, PhotoMetadata), Box<dyn std::error::Error>> {
+    let file_bytes = download(filename).await?;
+    // Bytes other than the declared ones are not processed.
+    if let Err(e) = verify_hash(&file_bytes, user_info.filehash()) {
+        remove_from_feeder(filename).await?;
+        return Err(e.into());
+    }
 
-                let mut img_embeddings = GalleryEmbeddings::new(thumbnail_name.clone(), embeddings);
-                img_embeddings.create(&db_pool).await?;
+    // Animations are shown by their most representative frame. The sampled
+    // frames are embedded too, so searches match what happens mid-animation.
+    let animation = decode_animation(&file_bytes)?;
+    let (i, frame_embeddings) = match &animation {
+        Some(frames) => {
+            let sampled = sample_indices(frames.len(), SAMPLED_FRAMES);
+            let embeddings = get_imgs_embeddings(
+                sampled.iter().map(|f| create_thumbnail(&frames[*f].image).image).collect(),
+            )?;
+            let poster = sampled[representative(&embeddings)];
+            log::info!("Poster frame {poster} of {}", frames.len());
 
-                let moved_feeded_img_filepath = move_to_ragged(&msg.filename).await?;
+            let frame_embeddings: Vec<FrameEmbedding> = sampled
+                .iter()
+                .zip(embeddings)
+                .map(|(f, embedding)| FrameEmbedding { frame_index: *f as i32, embedding })
+                .collect();
+            // The frames are downscaled, the poster is decoded again for the renditions.
+            (decode_frame(&file_bytes, poster)?, frame_embeddings)
+        }
+        None => (image_from_bytes(&file_bytes)?, vec![]),
+    };
+    let mut img_metadata = read_metadata(&file_bytes);
+    if let (Some(geocoder), Some(latitude), Some(longitude)) = (geocoder, img_metadata.latitude, img_metadata.longitude)
+        && let Some(place) = geocoder.nearest(latitude, longitude)
+    {
+        img_metadata.city = Some(place.city.clone());
+        img_metadata.region = place.region.clone();
+        img_metadata.country = place.country.clone();
+    }
+    let thumbnail_512p = create_thumbnail(&i);
 
-                img_gallery.update_with_processed(&db_pool, &moved_feeded_img_filepath,
-                    NewThumbnail{
-                        path: &thumbnail_name, height: *thumbnail_512p.height() as i32, width: *thumbnail_512p.width() as i32, ratio: &thumbnail_512p.ratio_as_str() }, NewEmbeddings{embeddings_id: img_embeddings.id()})
-                    .await?;
+    // BlobStore thumbnail image.
+    let mut webp_bytes: Vec<u8> = Vec::new();
+    let _ =
+        thumbnail_512p.image().write_to(&mut Cursor::new(&mut webp_bytes), image::ImageFormat::WebP);
+    let thumbnail_name = format!("thumbnail/{}.webp", uuid::Uuid::new_v4().to_string());
 
+    let _ = upload(&thumbnail_name, webp_bytes, Some(bucket_to_upload)).await?;
+    set_progress(db_pool, user_info.id(), ProcessingStatus::ThumbnailReady, None).await;
 
-                if let Err(e) = genai_tx.send((thumbnail_512p.image().clone(), img_embeddings)){
-                    log::error!("Failed to send thumbnail to genai thread\n{e:?}");
-                }
-            },
-            Some(msg) = genai_rx.recv() => {
-                let ( img_thumbnail, img_embeddings) = msg;
+    // Generate embeddings from thumbnail image.
+    let embeddings = get_img_embeddings(thumbnail_512p.image().clone())?;
 
-                let structured = match llm_to_use.as_str() {
-                    "openai" => {
-                         let img_str = to_base64(&img_thumbnail);
-                         let structured_output = fetch_description(&img_str, ImagePrompt::SemiStructured).await?;
-                         structured_output
-                    },
-                    _ => {
-                        // Ollama
-                        let ollama_str = to_llava_base64(&img_thumbnail);
-                        let ollama_structured = fetch_llava_description(&ollama_str, ImagePrompt::SemiStructured).await?;
-                         ollama_structured
-                    }
-                };
+    // Create db records
+    let mut img_gallery = Gallery::new(filename).create(db_pool).await?;
 
-                let structures = match serde_json::from_str::<SemiStructuredMessage>(&structured) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        log::error!("received from LLM {}. \n and error {e:?}", structured);
-                        continue;
-                    }
-                };
+    // Embedded keywords and place names are searchable right away, the LLM
+    // tags are merged with them once the photo is described.
+    let mut img_embeddings = GalleryEmbeddings::new(thumbnail_name.clone(), embeddings)
+        .set_keywords(img_metadata.tags());
+    img_embeddings.create(db_pool).await?;
 
-                img_embeddings
-                    .link_genai_descriptors(&db_pool, &structures.tags, &structures.description, &structures.theme, &structures.alt, &structures.caption)
-                    .await?;
-            },
+    let moved_feeded_img_filepath = move_to_ragged(filename).await?;
+
+    img_gallery.update_with_processed(db_pool, &moved_feeded_img_filepath,
+        NewThumbnail{
+            path: &thumbnail_name, height: *thumbnail_512p.height() as i32, width: *thumbnail_512p.width() as i32, ratio: &thumbnail_512p.ratio_as_str() }, NewEmbeddings{embeddings_id: img_embeddings.id()})
+        .await?;
+    // The photo shows up in the gallery once it is linked to the upload.
+    user_info.set_gallery_id(db_pool, &img_gallery.id()).await?;
+
+    // Optional steps, the photo is usable without them.
+    let gallery_id = *img_gallery.id();
+    enrichment("renditions", &gallery_id, create_renditions(db_pool, bucket_to_upload, rendition_settings, &i, &gallery_id, &thumbnail_name, &thumbnail_512p).await);
+    if let Some(frames) = &animation {
+        enrichment("frame embeddings", &gallery_id, FrameEmbedding::save_for_gallery(db_pool, &gallery_id, &frame_embeddings).await);
+        enrichment("animated preview", &gallery_id, create_animated_preview(db_pool, bucket_to_upload, frames, &gallery_id).await);
+    }
+    let (original_width, original_height) = i.dimensions();
+    enrichment("original dimensions", &gallery_id, img_gallery.set_original_dimensions(db_pool, original_width as i32, original_height as i32).await);
+    // Stored as the signed bigint bit pattern
+    enrichment("perceptual hash", &gallery_id, img_gallery.set_perceptual_hash(db_pool, perceptual_hash(&i) as i64).await);
+    enrichment("blurhash", &gallery_id, img_gallery.set_blurhash(db_pool, &blurhash(thumbnail_512p.image())).await);
+    enrichment("palette", &gallery_id, PaletteColor::save_for_embeddings(db_pool, img_embeddings.id(), &dominant_colors(thumbnail_512p.image())).await);
+    enrichment("metadata", &gallery_id, img_metadata.save(db_pool, &gallery_id).await);
+    // RAW+JPEG siblings and burst frames are shown as one gallery entry.
+    let stack = PhotoStack::assign(db_pool, &gallery_id, BURST_WINDOW_SECONDS, BURST_MAX_DISTANCE).await;
+    if let Some(Some(stack)) = enrichment("stack", &gallery_id, stack) {
+        log::info!("Stacked {} as {:?} into {}", gallery_id, stack.kind(), stack.id());
+    }
+    set_progress(db_pool, user_info.id(), ProcessingStatus::Embedded, None).await;
+
+    Ok((thumbnail_512p.image().clone(), img_embeddings, img_metadata))
+}
+
+/// Result of an optional processing step. Like the geocoding, a failure is
+/// only logged and the photo stays in the gallery without it.
+fn enrichment<T, E: std::fmt::Debug>(step: &str, gallery_id: &uuid::Uuid, result: Result<T, E>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            log::error!("Failed to add the {step} of {gallery_id}\n{e:?}");
+            None
         }
     }
 }
+
+/// Asks the LLM for the thumbnail descriptors and links them to the embeddings.
+/// The keywords embedded in the file and the place names are kept next to the generated tags.
+async fn describe(
+    db_pool: &DbConn,
+    llm_to_use: &str,
+    img_thumbnail: &DynamicImage,
+    img_embeddings: &GalleryEmbeddings,
+    img_metadata: &PhotoMetadata,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let prompt = if img_metadata.keywords.is_empty() && img_metadata.caption.is_none() {
+        ImagePrompt::SemiStructured
+    } else {
+        ImagePrompt::SemiStructuredWithContext {
+            keywords: img_metadata.keywords.clone(),
+            caption: img_metadata.caption.clone(),
+        }
+    };
+    let structured = match llm_to_use {
+        "openai" => {
+             let img_str = to_base64(img_thumbnail);
+             let structured_output = fetch_description(&img_str, prompt).await?;
+             structured_output
+        },
+        _ => {
+            // Ollama
+            let ollama_str = to_llava_base64(img_thumbnail);
+            let ollama_structured = fetch_llava_description(&ollama_str, prompt).await?;
+             ollama_structured
+        }
+    };
+
+    let structures = match serde_json::from_str::<SemiStructuredMessage>(&structured) {
+        Ok(s) => s,
+        Err(e) => {
+            log::error!("received from LLM {}. \n and error {e:?}", structured);
+            return Err(e.into());
+        }
+    };
+
+    let tags = merge_tags(&structures.tags, &img_metadata.tags());
+    img_embeddings
+        .link_genai_descriptors(db_pool, &tags, &structures.description, &structures.theme, &structures.alt, &structures.caption)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,330 @@
+use std::io::Cursor;
+
+use db_storage::models::photo_metadata::PhotoMetadata;
+use exif::{Exif, In, Tag, Value};
+use time::{Date, Month, PrimitiveDateTime, Time};
+
+/// Reads the EXIF, IPTC and XMP metadata of the original file.
+/// Files without metadata, or with a broken one, return the defaults.
+pub fn read_metadata(bytes: &[u8]) -> PhotoMetadata {
+    let mut metadata = PhotoMetadata::default();
+
+    match exif::Reader::new().read_from_container(&mut Cursor::new(bytes)) {
+        Ok(exif) => read_exif(&exif, &mut metadata),
+        Err(e) => log::debug!("No EXIF metadata. {e}"),
+    };
+
+    let iptc = read_iptc(bytes);
+    let xmp = read_xmp(bytes);
+    metadata.keywords = merge_tags(&xmp.keywords, &iptc.keywords);
+    metadata.caption = xmp.caption.or(iptc.caption);
+
+    metadata
+}
+
+fn read_exif(exif: &Exif, metadata: &mut PhotoMetadata) {
+    let field = |tag: Tag| exif.get_field(tag, In::PRIMARY).map(|f| &f.value);
+
+    // Capture time first, then digitization and file change. Each with its own offset.
+    let taken = [
+        (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+        (Tag::DateTimeDigitized, Tag::OffsetTimeDigitized),
+        (Tag::DateTime, Tag::OffsetTime),
+    ]
+    .into_iter()
+    .find_map(|(date_tag, offset_tag)| {
+        let value = field(date_tag).and_then(ascii)?;
+        let taken = exif::DateTime::from_ascii(value.as_bytes()).ok()?;
+        Some((taken, offset_tag))
+    });
+    if let Some((mut taken, offset_tag)) = taken {
+        if let Some(offset) = field(offset_tag).and_then(ascii) {
+            // Left unknown when malformed
+            let _ = taken.parse_offset(offset.as_bytes());
+        }
+        metadata.taken_at = date_time(&taken);
+        metadata.taken_at_offset = taken.offset;
+    }
+
+    metadata.camera_make = field(Tag::Make).and_then(ascii);
+    metadata.camera_model = field(Tag::Model).and_then(ascii);
+    metadata.lens_model = field(Tag::LensModel).and_then(ascii);
+    metadata.exposure_time = field(Tag::ExposureTime).and_then(|v| rational(v, 0));
+    metadata.f_number = field(Tag::FNumber).and_then(|v| rational(v, 0));
+    metadata.focal_length = field(Tag::FocalLength).and_then(|v| rational(v, 0));
+    metadata.iso = field(Tag::PhotographicSensitivity)
+        .and_then(|v| v.get_uint(0))
+        .map(|iso| iso as i32);
+    metadata.orientation = field(Tag::Orientation)
+        .and_then(|v| v.get_uint(0))
+        .filter(|o| (1..=8).contains(o))
+        .map(|o| o as i16);
+
+    metadata.latitude = coordinate(field(Tag::GPSLatitude), field(Tag::GPSLatitudeRef), "S");
+    metadata.longitude = coordinate(field(Tag::GPSLongitude), field(Tag::GPSLongitudeRef), "W");
+    metadata.altitude = field(Tag::GPSAltitude)
+        .and_then(|v| rational(v, 0))
+        .map(
+            |altitude| match field(Tag::GPSAltitudeRef).and_then(|v| v.get_uint(0)) {
+                // Below the sea level
+                Some(1) => -altitude,
+                _ => altitude,
+            },
+        );
+}
+
+fn ascii(value: &Value) -> Option<String> {
+    match value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|v| {
+                String::from_utf8_lossy(v)
+                    .trim_matches(['\0', ' '])
+                    .to_string()
+            })
+            .filter(|v| !v.is_empty()),
+        _ => None,
+    }
+}
+
+fn rational(value: &Value, index: usize) -> Option<f64> {
+    match value {
+        Value::Rational(values) => values
+            .get(index)
+            .filter(|r| r.denom != 0)
+            .map(|r| r.to_f64()),
+        _ => None,
+    }
+}
+
+/// Degrees, minutes and seconds into signed decimal degrees.
+fn coordinate(value: Option<&Value>, reference: Option<&Value>, negative: &str) -> Option<f64> {
+    let value = value?;
+    let degrees = rational(value, 0)?;
+    let minutes = rational(value, 1).unwrap_or(0.0);
+    let seconds = rational(value, 2).unwrap_or(0.0);
+    let decimal = degrees + minutes / 60.0 + seconds / 3600.0;
+
+    match reference.and_then(ascii) {
+        Some(r) if r.eq_ignore_ascii_case(negative) => Some(-decimal),
+        _ => Some(decimal),
+    }
+}
+
+fn date_time(taken: &exif::DateTime) -> Option<PrimitiveDateTime> {
+    let date = Date::from_calendar_date(
+        taken.year as i32,
+        Month::try_from(taken.month).ok()?,
+        taken.day,
+    )
+    .ok()?;
+    let time = Time::from_hms_nano(
+        taken.hour,
+        taken.minute,
+        taken.second,
+        taken.nanosecond.unwrap_or(0),
+    )
+    .ok()?;
+
+    Some(PrimitiveDateTime::new(date, time))
+}
+
+/// Keywords and caption written by the cataloguing tools.
+#[derive(Debug, Default, PartialEq)]
+struct Annotations {
+    keywords: Vec<String>,
+    caption: Option<String>,
+}
+
+/// XMP packet, as written by Lightroom, digiKam and the like.
+/// Reads `dc:subject` as keywords and `dc:description` as caption.
+fn read_xmp(bytes: &[u8]) -> Annotations {
+    let start = match find(bytes, b"<x:xmpmeta") {
+        Some(s) => s,
+        None => return Annotations::default(),
+    };
+    let end = find(&bytes[start..], b"</x:xmpmeta>").map_or(bytes.len(), |e| start + e);
+    let packet = String::from_utf8_lossy(&bytes[start..end]);
+
+    Annotations {
+        keywords: xmp_items(&packet, "dc:subject"),
+        caption: xmp_items(&packet, "dc:description").into_iter().next(),
+    }
+}
+
+/// Text of the `rdf:li` items of an XMP property.
+fn xmp_items(packet: &str, property: &str) -> Vec<String> {
+    let open = format!("<{property}>");
+    let close = format!("</{property}>");
+    let content = match packet.split_once(&open) {
+        Some((_, rest)) => rest.split_once(&close).map_or(rest, |(c, _)| c),
+        None => return vec![],
+    };
+
+    content
+        .split("<rdf:li")
+        .skip(1)
+        .filter_map(|item| {
+            let (_, text) = item.split_once('>')?;
+            let (text, _) = text.split_once("</rdf:li>")?;
+            let text = unescape_xml(text.trim());
+            (!text.is_empty()).then_some(text)
+        })
+        .collect()
+}
+
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// IPTC IIM block of a JPEG, inside the Photoshop APP13 segment.
+/// Reads the keywords (2:25) and the caption (2:120).
+fn read_iptc(bytes: &[u8]) -> Annotations {
+    let mut annotations = Annotations::default();
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return annotations;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+        let marker = bytes[pos + 1];
+        let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        // Start of scan, metadata segments come before it.
+        if marker == 0xDA || length < 2 {
+            break;
+        }
+        let segment = &bytes[(pos + 4).min(bytes.len())..(pos + 2 + length).min(bytes.len())];
+        if marker == 0xED
+            && let Some(resources) = segment.strip_prefix(b"Photoshop 3.0\0")
+        {
+            read_iim(photoshop_resource(resources, 0x0404), &mut annotations);
+        }
+        pos += 2 + length;
+    }
+
+    annotations
+}
+
+/// Data of the `8BIM` image resource with the given id.
+fn photoshop_resource(mut resources: &[u8], id: u16) -> &[u8] {
+    while resources.len() >= 8 && resources.starts_with(b"8BIM") {
+        let resource_id = u16::from_be_bytes([resources[4], resources[5]]);
+        // Pascal string name, padded to an even length
+        let name_len = resources[6] as usize;
+        let name_end = 6 + ((name_len + 2) & !1);
+        if resources.len() < name_end + 4 {
+            break;
+        }
+        let size = u32::from_be_bytes([
+            resources[name_end],
+            resources[name_end + 1],
+            resources[name_end + 2],
+            resources[name_end + 3],
+        ]) as usize;
+        let data_start = name_end + 4;
+        let data_end = (data_start + size).min(resources.len());
+        if resource_id == id {
+            return &resources[data_start..data_end];
+        }
+        resources = &resources[(data_start + size + (size & 1)).min(resources.len())..];
+    }
+
+    &[]
+}
+
+fn read_iim(mut records: &[u8], annotations: &mut Annotations) {
+    while records.len() >= 5 && records[0] == 0x1C {
+        let (record, dataset) = (records[1], records[2]);
+        let size = u16::from_be_bytes([records[3], records[4]]) as usize;
+        // Extended sizes are only used for large binary datasets.
+        if size & 0x8000 != 0 {
+            break;
+        }
+        let value = &records[5..(5 + size).min(records.len())];
+        let text = String::from_utf8_lossy(value).trim().to_string();
+        match (record, dataset) {
+            (2, 25) if !text.is_empty() => annotations.keywords.push(text),
+            (2, 120) if !text.is_empty() => annotations.caption = Some(text),
+            _ => (),
+        }
+        records = &records[(5 + size).min(records.len())..];
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Tags of both lists, without repeating a tag in a different case.
+/// The order is kept, `first` goes first.
+pub fn merge_tags(first: &[String], second: &[String]) -> Vec<String> {
+    let mut merged: Vec<String> = vec![];
+    for tag in first.iter().chain(second.iter()) {
+        let tag = tag.trim();
+        if !tag.is_empty() && !merged.iter().any(|m| m.eq_ignore_ascii_case(tag)) {
+            merged.push(tag.to_string());
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_xmp_annotations() {
+        let packet = br#"<?xpacket begin=""?><x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description>
+            <dc:subject><rdf:Bag><rdf:li>beach</rdf:li><rdf:li>Sun &amp; sea</rdf:li></rdf:Bag></dc:subject>
+            <dc:description><rdf:Alt><rdf:li xml:lang="x-default">Summer at the coast</rdf:li></rdf:Alt></dc:description>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+        assert_eq!(
+            read_xmp(packet),
+            Annotations {
+                keywords: vec!["beach".into(), "Sun & sea".into()],
+                caption: Some("Summer at the coast".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn it_reads_iptc_annotations() {
+        let mut iim = vec![];
+        for (dataset, value) in [(25u8, "dog"), (25, "park"), (120, "Walk")] {
+            iim.extend_from_slice(&[0x1C, 2, dataset]);
+            iim.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            iim.extend_from_slice(value.as_bytes());
+        }
+        let mut app13 = b"Photoshop 3.0\08BIM\x04\x04\0\0".to_vec();
+        app13.extend_from_slice(&(iim.len() as u32).to_be_bytes());
+        app13.extend_from_slice(&iim);
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xED];
+        jpeg.extend_from_slice(&((app13.len() + 2) as u16).to_be_bytes());
+        jpeg.extend_from_slice(&app13);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02]);
+
+        assert_eq!(
+            read_iptc(&jpeg),
+            Annotations {
+                keywords: vec!["dog".into(), "park".into()],
+                caption: Some("Walk".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn it_merges_tags_ignoring_case() {
+        let merged = merge_tags(
+            &["Beach".into(), "sea".into()],
+            &["beach".into(), " dog ".into(), "".into()],
+        );
+
+        assert_eq!(merged, vec!["Beach", "sea", "dog"]);
+    }
+}
@@ -0,0 +1,135 @@
+use db_storage::models::palette::{Lab, PaletteColor};
+use image::DynamicImage;
+
+/// Colours kept at most per image.
+const PALETTE_SIZE: usize = 5;
+/// Longest edge of the copy the colours are taken from.
+const SAMPLE_EDGE: u32 = 64;
+const MAX_ITERATIONS: usize = 20;
+/// Clusters smaller than this are noise, not a dominant colour.
+const MIN_SHARE: f32 = 0.02;
+/// Seeds closer than this to the others would split a single colour.
+const MIN_SEED_DISTANCE: f32 = 2.0;
+
+/// Dominant colours of the image, most dominant first.
+/// k-means over the CIELAB values of a small copy. The seeds are the points
+/// farthest from the previous ones, so the same image gives the same palette.
+/// Transparent pixels are left out.
+pub fn dominant_colors(img: &DynamicImage) -> Vec<PaletteColor> {
+    let pixels: Vec<Lab> = img
+        .thumbnail(SAMPLE_EDGE, SAMPLE_EDGE)
+        .to_rgba8()
+        .pixels()
+        .filter(|p| p[3] >= 128)
+        .map(|p| Lab::from_rgb([p[0], p[1], p[2]]))
+        .collect();
+    let Some(center) = mean(pixels.iter()) else {
+        return vec![];
+    };
+
+    let mut centroids = vec![center];
+    while centroids.len() < PALETTE_SIZE {
+        let (farthest, distance) = pixels
+            .iter()
+            .map(|p| (p, nearest(&centroids, p).1))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((&pixels[0], 0.0));
+        if distance < MIN_SEED_DISTANCE {
+            break;
+        }
+        centroids.push(*farthest);
+    }
+
+    let mut assignments = vec![usize::MAX; pixels.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (assigned, pixel) in assignments.iter_mut().zip(&pixels) {
+            let cluster = nearest(&centroids, pixel).0;
+            changed |= *assigned != cluster;
+            *assigned = cluster;
+        }
+        if !changed {
+            break;
+        }
+        for (cluster, centroid) in centroids.iter_mut().enumerate() {
+            let members = pixels
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, assigned)| **assigned == cluster)
+                .map(|(pixel, _)| pixel);
+            // Empty clusters keep their seed and end up with no share.
+            if let Some(updated) = mean(members) {
+                *centroid = updated;
+            }
+        }
+    }
+
+    let mut palette: Vec<PaletteColor> = centroids
+        .into_iter()
+        .enumerate()
+        .map(|(cluster, color)| PaletteColor {
+            color,
+            share: assignments.iter().filter(|a| **a == cluster).count() as f32
+                / pixels.len() as f32,
+        })
+        .filter(|c| c.share >= MIN_SHARE)
+        .collect();
+    palette.sort_by(|a, b| b.share.total_cmp(&a.share));
+    palette
+}
+
+/// Position of the closest centroid and its distance.
+fn nearest(centroids: &[Lab], pixel: &Lab) -> (usize, f32) {
+    centroids
+        .iter()
+        .map(|c| c.distance(pixel))
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((0, f32::MAX))
+}
+
+/// None when there are no colours.
+fn mean<'a>(colors: impl Iterator<Item = &'a Lab>) -> Option<Lab> {
+    let (mut sum, mut count) = ([0f32; 3], 0);
+    for color in colors {
+        sum[0] += color.l;
+        sum[1] += color.a;
+        sum[2] += color.b;
+        count += 1;
+    }
+    if count == 0 {
+        return None;
+    }
+    let [l, a, b] = sum.map(|s| s / count as f32);
+    Some(Lab { l, a, b })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db_storage::models::palette::ColorName;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn it_extracts_the_dominant_colors() {
+        let img = RgbImage::from_fn(128, 128, |x, _| {
+            if x < 96 {
+                Rgb([30, 60, 200])
+            } else {
+                Rgb([220, 30, 30])
+            }
+        });
+
+        let palette = dominant_colors(&DynamicImage::ImageRgb8(img));
+
+        assert_eq!(palette.len(), 2);
+        assert_eq!(palette[0].color.name(), ColorName::Blue);
+        assert!((palette[0].share - 0.75).abs() < 0.02);
+        assert_eq!(palette[1].color.name(), ColorName::Red);
+    }
+
+    #[test]
+    fn it_skips_transparent_images() {
+        assert!(dominant_colors(&DynamicImage::new_rgba8(8, 8)).is_empty());
+    }
+}
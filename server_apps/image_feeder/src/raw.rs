@@ -0,0 +1,259 @@
+use std::collections::{HashMap, HashSet};
+
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageFormat};
+
+use crate::errors::ImageProcessError;
+
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_PHOTOMETRIC: u16 = 0x0106;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+const TAG_DNG_VERSION: u16 = 0xC612;
+
+/// Old-style and new-style JPEG compression.
+const COMPRESSION_JPEG: [u32; 2] = [6, 7];
+/// Sensor data, never a preview.
+const PHOTOMETRIC_RAW: [u32; 2] = [32803, 34892];
+/// Guards against IFD loops in damaged files.
+const MAX_IFDS: usize = 32;
+const MAX_VALUES: u32 = 64;
+
+/// Camera RAW file in a TIFF container: DNG, CR2, NEF, ARW.
+/// It is not demosaiced, the JPEG preview the camera embeds is used instead.
+/// That preview is what the camera shows and it is usually full size or close.
+#[derive(Debug)]
+pub struct RawFile<'a> {
+    bytes: &'a [u8],
+    /// `(offset, length)` of the embedded JPEG streams.
+    previews: Vec<(usize, usize)>,
+    orientation: Option<u8>,
+}
+
+impl<'a> RawFile<'a> {
+    /// None when the bytes are not a camera RAW file. Plain TIFF images are left
+    /// to the image decoder.
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        let tiff = Tiff::new(bytes)?;
+        // CR2 marks its header, the other formats are told apart by their tags.
+        let mut is_raw = bytes.get(8..10) == Some(b"CR".as_slice());
+        let mut previews = vec![];
+        let mut orientation = None;
+
+        let ifd0 = tiff.u32_at(4)?;
+        let mut pending = vec![ifd0];
+        let mut visited = HashSet::new();
+        while let Some(offset) = pending.pop() {
+            if offset == 0 || !visited.insert(offset) || visited.len() > MAX_IFDS {
+                continue;
+            }
+            let Some((tags, next)) = tiff.ifd(offset as usize) else {
+                continue;
+            };
+            pending.push(next);
+            pending.extend(tags.get(&TAG_SUB_IFDS).into_iter().flatten());
+
+            if offset == ifd0 {
+                orientation = tags
+                    .get(&TAG_ORIENTATION)
+                    .and_then(|o| o.first())
+                    .map(|o| *o as u8);
+            }
+            let first = |tag: u16| tags.get(&tag).and_then(|values| values.first()).copied();
+            is_raw |= tags.contains_key(&TAG_DNG_VERSION)
+                || first(TAG_PHOTOMETRIC).is_some_and(|p| PHOTOMETRIC_RAW.contains(&p));
+
+            if let (Some(start), Some(length)) = (first(TAG_JPEG_OFFSET), first(TAG_JPEG_LENGTH)) {
+                previews.push((start as usize, length as usize));
+            }
+            let jpeg_strip = tags.get(&TAG_STRIP_OFFSETS).is_some_and(|s| s.len() == 1)
+                && first(TAG_COMPRESSION).is_some_and(|c| COMPRESSION_JPEG.contains(&c))
+                && first(TAG_PHOTOMETRIC).is_none_or(|p| !PHOTOMETRIC_RAW.contains(&p));
+            if let (true, Some(start), Some(length)) = (
+                jpeg_strip,
+                first(TAG_STRIP_OFFSETS),
+                first(TAG_STRIP_BYTE_COUNTS),
+            ) {
+                previews.push((start as usize, length as usize));
+            }
+        }
+
+        if !is_raw {
+            return None;
+        }
+        // Largest first, it is the one with the most pixels.
+        previews.retain(|(start, length)| {
+            bytes
+                .get(*start..start.saturating_add(*length))
+                .is_some_and(|jpeg| jpeg.starts_with(&[0xFF, 0xD8]))
+        });
+        previews.sort_by(|a, b| b.1.cmp(&a.1));
+        previews.dedup();
+
+        Some(Self {
+            bytes,
+            previews,
+            orientation,
+        })
+    }
+
+    /// Largest preview that decodes, turned upright with the RAW orientation.
+    pub fn preview(&self) -> Result<DynamicImage, ImageProcessError> {
+        for (start, length) in self.previews.iter() {
+            let jpeg = &self.bytes[*start..start + length];
+            match image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg) {
+                Ok(mut img) => {
+                    if let Some(orientation) = self.orientation.and_then(Orientation::from_exif) {
+                        img.apply_orientation(orientation);
+                    }
+                    return Ok(img);
+                }
+                // Lossless JPEG sensor data in CR2 and DNG files
+                Err(e) => log::debug!("Skipping RAW preview at {start}. {e}"),
+            }
+        }
+
+        log::error!("No decodable preview in the RAW file");
+        Err(ImageProcessError::ImageLoad)
+    }
+}
+
+/// Reads the IFDs of a TIFF container.
+struct Tiff<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(bytes: &'a [u8]) -> Option<Self> {
+        let little_endian = match bytes.get(0..4)? {
+            [b'I', b'I', 42, 0] => true,
+            [b'M', b'M', 0, 42] => false,
+            _ => return None,
+        };
+        Some(Self {
+            bytes,
+            little_endian,
+        })
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let b: [u8; 2] = self.bytes.get(offset..offset + 2)?.try_into().ok()?;
+        Some(match self.little_endian {
+            true => u16::from_le_bytes(b),
+            false => u16::from_be_bytes(b),
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let b: [u8; 4] = self.bytes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match self.little_endian {
+            true => u32::from_le_bytes(b),
+            false => u32::from_be_bytes(b),
+        })
+    }
+
+    /// Tags of the IFD and the offset of the next one.
+    /// Only SHORT, LONG and IFD values are read, other tags map to no values.
+    fn ifd(&self, offset: usize) -> Option<(HashMap<u16, Vec<u32>>, u32)> {
+        let count = self.u16_at(offset)? as usize;
+        let mut tags = HashMap::new();
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+            let tag = self.u16_at(entry)?;
+            let field_type = self.u16_at(entry + 2)?;
+            let values_count = self.u32_at(entry + 4)?.min(MAX_VALUES);
+
+            let size = match field_type {
+                3 => 2,
+                4 | 13 => 4,
+                _ => {
+                    tags.insert(tag, vec![]);
+                    continue;
+                }
+            };
+            // Values that do not fit in the entry are stored elsewhere.
+            let start = match size * values_count as usize > 4 {
+                true => self.u32_at(entry + 8)? as usize,
+                false => entry + 8,
+            };
+            let values = (0..values_count as usize)
+                .filter_map(|v| match size {
+                    2 => self.u16_at(start + v * 2).map(u32::from),
+                    _ => self.u32_at(start + v * 4),
+                })
+                .collect();
+            tags.insert(tag, values);
+        }
+        let next = self.u32_at(offset + 2 + count * 12).unwrap_or(0);
+
+        Some((tags, next))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+    use std::io::Cursor;
+
+    fn entry(tag: u16, field_type: u16, value: u32) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend(tag.to_le_bytes());
+        bytes.extend(field_type.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(value.to_le_bytes());
+        bytes
+    }
+
+    /// DNG shaped file: IFD0 with the orientation, a preview sub IFD.
+    fn dng(jpeg: &[u8]) -> Vec<u8> {
+        let ifd0 = 8u32;
+        let sub_ifd = ifd0 + 2 + 3 * 12 + 4;
+        let jpeg_offset = sub_ifd + 2 + 4 * 12 + 4;
+
+        let mut bytes = b"II*\0".to_vec();
+        bytes.extend(ifd0.to_le_bytes());
+        bytes.extend(3u16.to_le_bytes());
+        bytes.extend(entry(TAG_ORIENTATION, 3, 6));
+        bytes.extend(entry(TAG_SUB_IFDS, 13, sub_ifd));
+        bytes.extend(entry(TAG_DNG_VERSION, 1, 0x0000_0401));
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(4u16.to_le_bytes());
+        bytes.extend(entry(TAG_COMPRESSION, 3, 7));
+        bytes.extend(entry(TAG_PHOTOMETRIC, 3, 6));
+        bytes.extend(entry(TAG_STRIP_OFFSETS, 4, jpeg_offset));
+        bytes.extend(entry(TAG_STRIP_BYTE_COUNTS, 4, jpeg.len() as u32));
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(jpeg);
+        bytes
+    }
+
+    #[test]
+    fn it_decodes_the_embedded_preview() {
+        let mut jpeg = vec![];
+        DynamicImage::new_rgb8(40, 20)
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+
+        let preview = RawFile::parse(&dng(&jpeg)).unwrap().preview().unwrap();
+
+        // Orientation 6, rotated to portrait
+        assert_eq!(preview.dimensions(), (20, 40));
+    }
+
+    #[test]
+    fn it_leaves_other_images_to_the_decoder() {
+        let mut tiff = vec![];
+        DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut Cursor::new(&mut tiff), ImageFormat::Tiff)
+            .unwrap();
+
+        assert!(RawFile::parse(&tiff).is_none());
+        assert!(RawFile::parse(b"\xFF\xD8\xFF\xE0").is_none());
+    }
+}
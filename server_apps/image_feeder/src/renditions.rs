@@ -0,0 +1,193 @@
+use db_storage::{
+    DbConn,
+    models::renditions::{Rendition, RenditionFormat},
+};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use uuid::Uuid;
+
+use crate::animation::{Frame, encode_animated_webp};
+use crate::bucket::upload;
+use crate::image_operations::{ImageData, encode, resize_longest_edge};
+
+const DEFAULT_SIZES: [u32; 4] = [256, 512, 1024, 2048];
+/// Animated previews are lossless, they are kept small.
+const ANIMATED_PREVIEW_EDGE: u32 = 320;
+const ANIMATED_PREVIEW_FRAMES: usize = 100;
+
+/// Renditions produced for every upload.
+#[derive(Debug, Clone)]
+pub struct RenditionSettings {
+    /// Longest edges, smallest first.
+    sizes: Vec<u32>,
+    avif: bool,
+}
+
+impl RenditionSettings {
+    /// `RENDITION_SIZES` comma separated longest edges, 256, 512, 1024 and 2048 by default.
+    /// AVIF is added when `RENDITION_AVIF` is "true".
+    pub fn from_env() -> Self {
+        let sizes = match std::env::var("RENDITION_SIZES") {
+            Ok(sizes) => parse_sizes(&sizes),
+            Err(_) => DEFAULT_SIZES.to_vec(),
+        };
+        let avif =
+            std::env::var("RENDITION_AVIF").is_ok_and(|avif| avif.eq_ignore_ascii_case("true"));
+        log::info!("Renditions {sizes:?}, AVIF {avif}");
+
+        Self { sizes, avif }
+    }
+}
+
+/// Lossy JPEG for photos. JPEG has no alpha, transparent images keep the
+/// lossless WebP that suits graphics anyway.
+fn rendition_formats(has_alpha: bool, avif: bool) -> Vec<RenditionFormat> {
+    let mut formats = vec![match has_alpha {
+        true => RenditionFormat::Webp,
+        false => RenditionFormat::Jpeg,
+    }];
+    if avif {
+        formats.push(RenditionFormat::Avif);
+    }
+    formats
+}
+
+/// Invalid entries are skipped. The defaults are used when none is valid.
+fn parse_sizes(sizes: &str) -> Vec<u32> {
+    let mut parsed: Vec<u32> = sizes
+        .split(',')
+        .filter_map(|size| match size.trim().parse::<u32>() {
+            Ok(size) if size > 0 => Some(size),
+            _ => {
+                log::warn!("Ignoring rendition size {size:?}");
+                None
+            }
+        })
+        .collect();
+    if parsed.is_empty() {
+        return DEFAULT_SIZES.to_vec();
+    }
+    parsed.sort();
+    parsed.dedup();
+    parsed
+}
+
+/// Longest edges to produce for an image of `longest` pixels. Images are not
+/// enlarged, the sizes above it are replaced by a single full size rendition.
+fn rendition_edges(sizes: &[u32], longest: u32) -> Vec<u32> {
+    let mut edges: Vec<u32> = sizes.iter().copied().filter(|s| *s < longest).collect();
+    if sizes.iter().any(|s| *s >= longest) {
+        edges.push(longest);
+    }
+    edges
+}
+
+fn image_format(format: RenditionFormat) -> ImageFormat {
+    match format {
+        RenditionFormat::Webp | RenditionFormat::AnimatedWebp => ImageFormat::WebP,
+        RenditionFormat::Avif => ImageFormat::Avif,
+        RenditionFormat::Jpeg => ImageFormat::Jpeg,
+    }
+}
+
+/// Resizes, uploads and records the renditions of the upright image.
+/// The WebP rendition of the thumbnail size is the thumbnail itself, already uploaded.
+pub async fn create_renditions(
+    db_pool: &DbConn,
+    bucket_to_upload: &str,
+    settings: &RenditionSettings,
+    img: &DynamicImage,
+    gallery_id: &Uuid,
+    thumbnail_path: &str,
+    thumbnail: &ImageData,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let thumbnail_edge = *thumbnail.width().max(thumbnail.height());
+
+    let (width, height) = img.dimensions();
+    let formats = rendition_formats(img.color().has_alpha(), settings.avif);
+    for edge in rendition_edges(&settings.sizes, width.max(height)) {
+        let resized = resize_longest_edge(img, edge);
+        for format in formats.iter() {
+            if *format == RenditionFormat::Webp && edge == thumbnail_edge {
+                Rendition::new(
+                    *gallery_id,
+                    *format,
+                    *thumbnail.width() as i32,
+                    *thumbnail.height() as i32,
+                    thumbnail_path.to_string(),
+                )
+                .save(db_pool)
+                .await?;
+                continue;
+            }
+            let bytes = encode(resized.image(), image_format(*format))?;
+            let path = format!("rendition/{gallery_id}/{edge}.{}", format.extension());
+            upload(&path, bytes, Some(bucket_to_upload)).await?;
+
+            Rendition::new(
+                *gallery_id,
+                *format,
+                *resized.width() as i32,
+                *resized.height() as i32,
+                path,
+            )
+            .save(db_pool)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Uploads and records the animated WebP preview of the frames.
+pub async fn create_animated_preview(
+    db_pool: &DbConn,
+    bucket_to_upload: &str,
+    frames: &[Frame],
+    gallery_id: &Uuid,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let bytes = encode_animated_webp(frames, ANIMATED_PREVIEW_EDGE, ANIMATED_PREVIEW_FRAMES)?;
+    let (width, height) = resize_longest_edge(&frames[0].image, ANIMATED_PREVIEW_EDGE)
+        .image()
+        .dimensions();
+    let path = format!("rendition/{gallery_id}/animated_{ANIMATED_PREVIEW_EDGE}.webp");
+    upload(&path, bytes, Some(bucket_to_upload)).await?;
+
+    Rendition::new(
+        *gallery_id,
+        RenditionFormat::AnimatedWebp,
+        width as i32,
+        height as i32,
+        path,
+    )
+    .save(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_does_not_enlarge_renditions() {
+        assert_eq!(
+            rendition_edges(&DEFAULT_SIZES, 4000),
+            vec![256, 512, 1024, 2048]
+        );
+        assert_eq!(rendition_edges(&DEFAULT_SIZES, 700), vec![256, 512, 700]);
+        assert_eq!(rendition_edges(&DEFAULT_SIZES, 512), vec![256, 512]);
+        assert_eq!(rendition_edges(&DEFAULT_SIZES, 100), vec![100]);
+        assert_eq!(parse_sizes("1024, 256,x,256"), vec![256, 1024]);
+        assert_eq!(parse_sizes(""), DEFAULT_SIZES.to_vec());
+    }
+
+    #[test]
+    fn it_keeps_webp_for_transparent_images() {
+        assert_eq!(rendition_formats(false, false), vec![RenditionFormat::Jpeg]);
+        assert_eq!(
+            rendition_formats(true, true),
+            vec![RenditionFormat::Webp, RenditionFormat::Avif]
+        );
+    }
+}
@@ -1,16 +1,40 @@
+use std::time::Duration;
+
+use db_storage::models::storage_cleanup::{FEEDER_BUCKET, RAGGED_BUCKET};
 use http::Method;
 use minio::s3::creds::StaticProvider;
 use minio::s3::http::BaseUrl;
+use minio::s3::types::S3Api;
 use minio::s3::{Client, ClientBuilder};
 
 use crate::config::Bucket as BucketConfig;
 use crate::error::Result;
 
+/// Immediate attempts of a delete before leaving it for the cleanup task.
+const DELETE_ATTEMPTS: u32 = 3;
+/// Original downloads are meant to be used right away.
+const ORIGINAL_URL_EXPIRY_SECS: u32 = 60;
+/// Lifetime of the signed urls, uploads included.
+const URL_EXPIRY_SECS: u32 = 300;
+
 pub enum Bucket {
     Feeder,
     Ragged,
 }
 
+impl TryFrom<&str> for Bucket {
+    type Error = crate::error::Error;
+
+    /// Logical bucket names stored in db.
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value {
+            FEEDER_BUCKET => Ok(Bucket::Feeder),
+            RAGGED_BUCKET => Ok(Bucket::Ragged),
+            b => Err(format!("Unknown bucket {b}").into()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 struct Buckets<'a> {
     feeder: &'a str,
@@ -42,7 +66,7 @@ impl<'a> BucketClient<'a> {
                 feeder: config.feeder_bucket(),
                 ragged: config.ragged_bucket(),
             },
-            expiry_url_secs: 300,
+            expiry_url_secs: URL_EXPIRY_SECS,
         })
     }
 
@@ -63,6 +87,58 @@ impl<'a> BucketClient<'a> {
         Ok(signed.url)
     }
 
+    /// Only a missing object is false, other failures are errors.
+    pub async fn object_exists(&self, filename: &str, bucket: Bucket) -> Result<bool> {
+        match self
+            .client
+            .stat_object(self.bucket(bucket), filename)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(minio::s3::error::Error::S3Error(e))
+                if matches!(e.code, minio::s3::error::ErrorCode::NoSuchKey) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn delete_object(&self, filename: &str, bucket: Bucket) -> Result<()> {
+        self.client
+            .delete_object(self.bucket(bucket), filename)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes with exponential backoff between attempts. Returns the last error.
+    pub async fn delete_object_with_retry(
+        &self,
+        filename: &str,
+        bucket: &str,
+    ) -> std::result::Result<(), String> {
+        let mut delay = Duration::from_millis(200);
+        let mut last_error = String::new();
+        for attempt in 1..=DELETE_ATTEMPTS {
+            let bucket = Bucket::try_from(bucket).map_err(|e| e.to_string())?;
+            match self.delete_object(filename, bucket).await {
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    log::warn!("Delete {filename} attempt {attempt} failed. {e:?}");
+                    last_error = e.to_string();
+                }
+            };
+            if attempt < DELETE_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+
+        Err(last_error)
+    }
+
     pub async fn get_download_signed_url(&self, filename: &str, bucket: Bucket) -> Result<String> {
         let signed = self
             .client
@@ -72,4 +148,64 @@ impl<'a> BucketClient<'a> {
             .await?;
         Ok(signed.url)
     }
+
+    /// Short lived download url. The browser saves it as `download_name`.
+    pub async fn get_original_signed_url(
+        &self,
+        filename: &str,
+        bucket: Bucket,
+        download_name: &str,
+    ) -> Result<String> {
+        let disposition = (
+            "response-content-disposition".to_string(),
+            content_disposition(download_name),
+        );
+        let signed = self
+            .client
+            .get_presigned_object_url(self.bucket(bucket), filename, Method::GET)
+            .expiry_seconds(ORIGINAL_URL_EXPIRY_SECS)
+            .extra_query_params(Some(FromIterator::from_iter([disposition])))
+            .send()
+            .await?;
+        Ok(signed.url)
+    }
+}
+
+/// `attachment` header value. Plain ascii fallback plus the RFC 5987 utf-8 name.
+fn content_disposition(name: &str) -> String {
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect();
+
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_content_disposition() {
+        assert_eq!(
+            content_disposition("IMG 01.jpg"),
+            "attachment; filename=\"IMG 01.jpg\"; filename*=UTF-8''IMG%2001.jpg"
+        );
+        assert_eq!(
+            content_disposition("año\".jpg"),
+            "attachment; filename=\"a_o_.jpg\"; filename*=UTF-8''a%C3%B1o%22.jpg"
+        );
+    }
 }
@@ -44,10 +44,28 @@ impl Bucket {
     }
 }
 
+#[derive(Getters)]
+pub struct Share {
+    /// Address of the http server as seen by the people opening the links.
+    public_url: String,
+}
+
+impl Share {
+    fn from_env() -> Self {
+        let public_url =
+            std::env::var("SHARE_PUBLIC_URL").unwrap_or("http://localhost:8000".to_string());
+
+        Self {
+            public_url: public_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
 #[derive(Getters)]
 pub struct Config {
     bucket: Bucket,
     db: Database,
+    share: Share,
 }
 
 impl Default for Config {
@@ -56,6 +74,7 @@ impl Default for Config {
         Self {
             bucket: Bucket::from_env().unwrap(),
             db: Database::from_env(),
+            share: Share::from_env(),
         }
     }
 }
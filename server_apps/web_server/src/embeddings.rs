@@ -0,0 +1,53 @@
+use std::sync::{Arc, Mutex};
+
+use fastembed::{EmbeddingModel, TextEmbedding, TextInitOptions};
+
+use crate::error::Result;
+
+/// CLIP text encoder.
+/// It must match the image model used by the feeder (ClipVitB32) so the text
+/// vectors land in the same space as `gallery_rag_embeddings.embedding`.
+#[derive(Clone)]
+pub struct TextEmbedder {
+    model: Arc<Mutex<TextEmbedding>>,
+}
+
+impl std::fmt::Debug for TextEmbedder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TextEmbedder").finish_non_exhaustive()
+    }
+}
+
+impl TextEmbedder {
+    /// Loads the model. On the first run it downloads it into `.fastembed_cache`.
+    pub fn new() -> Result<Self> {
+        let model = TextEmbedding::try_new(
+            TextInitOptions::new(EmbeddingModel::ClipVitB32).with_show_download_progress(true),
+        )?;
+
+        Ok(Self {
+            model: Arc::new(Mutex::new(model)),
+        })
+    }
+
+    /// Inference is cpu bound, it runs outside of the async workers.
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let model = self.model.clone();
+        let text = text.to_string();
+
+        let embeddings = tokio::task::spawn_blocking(move || {
+            let mut model = model
+                .lock()
+                .map_err(|_| "Text embeddings model is poisoned".to_string())?;
+            model.embed(vec![text], None).map_err(|e| e.to_string())
+        })
+        .await??;
+
+        let embedding = embeddings
+            .into_iter()
+            .next()
+            .ok_or("Text embeddings returned no results")?;
+
+        Ok(embedding)
+    }
+}
@@ -1,27 +1,241 @@
+use db_storage::models::{
+    DescriptorEdits, NearestQuery,
+    palette::{ColorFilter, ColorName, DEFAULT_COLOR_TOLERANCE, Lab},
+    share_links::ShareTarget,
+    timeline::BucketSize,
+    user_photos::{PhotoCursor, PhotoDetail, PhotoFilter},
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
+use uuid::Uuid;
 
 pub use gallery_view_rpc::gallery_view_server::{GalleryView, GalleryViewServer};
 use gallery_view_rpc::{
-    EmptyRequest, FilterGalleryRequest, FilterOptionResponse, GalleryImagesResponse,
-    SignedLinkResponse, UploadImageRequest,
+    Album, AlbumCoverRequest, AlbumPhotosRequest, AlbumPhotosResponse, AlbumRequest,
+    AlbumsResponse, CreateAlbumRequest, CreateShareLinkRequest, DeletePhotoRequest,
+    DescriptorField, DescriptorSource, DescriptorSources, Dimensions, DuplicateGroup,
+    DuplicateGroupsResponse, DuplicatePhoto, DuplicateResolution, EmptyRequest, EmptyResponse,
+    FacetCount, FilterGalleryRequest, FilterOptionResponse, FindSimilarRequest,
+    GalleryImagesResponse, GetPhotoRequest, ImageSource, ListDuplicatesRequest, MapCluster,
+    MapClustersRequest, MapClustersResponse, MatchSignal, MemoriesRequest, MemoriesResponse,
+    Memory, MonthCount, PhotoDetailResponse, ProcessingStatus, RenameAlbumRequest,
+    ReorderAlbumsRequest, RequestUploadsRequest, RequestUploadsResponse, ResolveDuplicatesRequest,
+    ResolveDuplicatesResponse, ScoredGalleryImage, SearchGalleryRequest, SearchGalleryResponse,
+    ShareLink, ShareLinkRequest, ShareLinksResponse, SignedLinkResponse, StackCoverRequest,
+    StackRequest, TimelineBucket, TimelineBucketSize, TimelineRequest, TimelineResponse,
+    UpdatePhotoMetadataRequest, UploadImageRequest, UploadProgress, UploadRejection, UploadSlot,
+    WatchUploadsRequest, create_share_link_request, upload_slot,
 };
 
 use crate::{
     bucket::BucketClient,
+    embeddings::TextEmbedder,
     gallery_view::{gallery_view_rpc::GalleryImage, model::FileUpload},
-    user_auth::SessionValidator,
+    user_auth::{SessionValidator, UserId},
 };
 
 pub mod gallery_view_rpc {
     tonic::include_proto!("gallery_view");
 }
 
+/// Page size used when the client does not provide one.
+const DEFAULT_PAGE_SIZE: i64 = 50;
+/// Upper bound of the page size, each item requires a signed url.
+const MAX_PAGE_SIZE: i64 = 200;
+/// Results returned by a search when the client does not provide a size.
+const DEFAULT_SEARCH_SIZE: i64 = 20;
+
+/// Nearest neighbours options shared by the search RPCs.
+fn nearest_query(size: Option<i32>, min_similarity: Option<f64>) -> NearestQuery {
+    NearestQuery {
+        limit: size.map_or(DEFAULT_SEARCH_SIZE, |s| (s as i64).clamp(1, MAX_PAGE_SIZE)),
+        // Cosine distance is `1 - similarity`
+        max_distance: min_similarity.map(|s| 1.0 - s.clamp(-1.0, 1.0)),
+    }
+}
+
+/// Share link lifetime when the client does not provide one.
+const DEFAULT_SHARE_HOURS: u32 = 24 * 7;
+const MAX_SHARE_HOURS: u32 = 24 * 90;
+/// Length of the share tokens, alphanumeric.
+const SHARE_TOKEN_LEN: usize = 32;
+
+/// How often the upload progress is read while watching.
+const WATCH_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// Watch streams are closed after this time. The client may reconnect.
+const WATCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// Overlap between polls. Covers updates committed after the previous poll
+/// with an earlier timestamp.
+const WATCH_OVERLAP: time::Duration = time::Duration::seconds(5);
+
+/// Files accepted by a single `RequestUploads` call.
+const MAX_BATCH_UPLOADS: usize = 1000;
+/// Pending uploads can be requested again after this, far past the upload
+/// url lifetime so a transfer in flight lands first.
+const STALE_UPLOAD_SECS: i64 = 60 * 60;
+
+/// Longest album name accepted.
+const MAX_ALBUM_NAME_LEN: usize = 120;
+
+/// Photos per year returned by `Memories` when the client does not provide a size.
+const DEFAULT_MEMORIES_SIZE: i64 = 20;
+/// Widest UTC offset in use, 14 hours.
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
+/// Clusters across a 256px map tile. A tile spans 360 / 2^zoom degrees.
+const MAP_CELLS_PER_TILE: f64 = 8.0;
+const MAX_MAP_ZOOM: i32 = 22;
+/// Upper bound of the clusters returned, each one requires a signed url.
+const MAX_MAP_CLUSTERS: i64 = 500;
+
+/// Keywords returned by `FilterOptions`.
+const TOP_KEYWORDS: i64 = 20;
+
+/// Highest hash distance accepted by `ListDuplicates`. Beyond it unrelated
+/// photos start to group.
+const MAX_DUPLICATE_DISTANCE: i32 = 16;
+
+fn album_name(name: &str) -> std::result::Result<String, Status> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_ALBUM_NAME_LEN {
+        return Err(Status::invalid_argument(format!(
+            "name must have between 1 and {MAX_ALBUM_NAME_LEN} characters"
+        )));
+    }
+    Ok(name.to_string())
+}
+
+fn album_id(id: &str) -> std::result::Result<Uuid, Status> {
+    Uuid::parse_str(id).map_err(|_| Status::invalid_argument("id is not a valid album id"))
+}
+
+fn stack_id(id: &str) -> std::result::Result<Uuid, Status> {
+    Uuid::parse_str(id).map_err(|_| Status::invalid_argument("id is not a valid stack id"))
+}
+
+fn gallery_ids(ids: &[String]) -> std::result::Result<Vec<Uuid>, Status> {
+    ids.iter()
+        .map(|id| {
+            Uuid::parse_str(id)
+                .map_err(|_| Status::invalid_argument(format!("{id} is not a valid gallery id")))
+        })
+        .collect()
+}
+
+/// The web client sends empty strings for unset filters.
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value.as_ref().filter(|v| !v.trim().is_empty()).cloned()
+}
+
+fn page_size(size: &Option<String>) -> std::result::Result<i64, Status> {
+    match non_empty(size).map(|s| s.trim().parse::<i64>()) {
+        None => Ok(DEFAULT_PAGE_SIZE),
+        Some(Ok(size)) => Ok(size.clamp(1, MAX_PAGE_SIZE)),
+        Some(Err(_)) => Err(Status::invalid_argument("size is not a number")),
+    }
+}
+
+fn utc_offset_minutes(offset: Option<i32>) -> std::result::Result<i32, Status> {
+    let offset = offset.unwrap_or(0);
+    if !(-MAX_UTC_OFFSET_MINUTES..=MAX_UTC_OFFSET_MINUTES).contains(&offset) {
+        return Err(Status::invalid_argument("utcOffsetMinutes is out of range"));
+    }
+    Ok(offset)
+}
+
+fn bounding_box(
+    req_info: &MapClustersRequest,
+) -> std::result::Result<db_storage::models::map::BoundingBox, Status> {
+    let latitudes = -90.0..=90.0;
+    let longitudes = -180.0..=180.0;
+    if !latitudes.contains(&req_info.min_latitude)
+        || !latitudes.contains(&req_info.max_latitude)
+        || !longitudes.contains(&req_info.min_longitude)
+        || !longitudes.contains(&req_info.max_longitude)
+    {
+        return Err(Status::invalid_argument("Coordinates are out of range"));
+    }
+    if req_info.min_latitude > req_info.max_latitude {
+        return Err(Status::invalid_argument(
+            "minLatitude is greater than maxLatitude",
+        ));
+    }
+
+    Ok(db_storage::models::map::BoundingBox {
+        min_latitude: req_info.min_latitude,
+        min_longitude: req_info.min_longitude,
+        max_latitude: req_info.max_latitude,
+        max_longitude: req_info.max_longitude,
+    })
+}
+
+/// A colour family name, or a hex colour matched within the tolerance.
+fn color_filter(color: &str, tolerance: Option<f32>) -> std::result::Result<ColorFilter, Status> {
+    if let Ok(name) = color.parse::<ColorName>() {
+        return Ok(ColorFilter::Named(name));
+    }
+    let color = Lab::from_hex(color).ok_or_else(|| {
+        Status::invalid_argument("color is not a colour name nor a #rrggbb colour")
+    })?;
+    let tolerance = tolerance.unwrap_or(DEFAULT_COLOR_TOLERANCE);
+    if !(0.0..=100.0).contains(&tolerance) {
+        return Err(Status::invalid_argument(
+            "colorTolerance must be between 0 and 100",
+        ));
+    }
+    Ok(ColorFilter::Near { color, tolerance })
+}
+
+fn photo_filter(req_info: &FilterGalleryRequest) -> std::result::Result<PhotoFilter, Status> {
+    let album = match non_empty(&req_info.album) {
+        Some(album) => Some(
+            Uuid::parse_str(&album)
+                .map_err(|_| Status::invalid_argument("album is not a valid album id"))?,
+        ),
+        None => None,
+    };
+    if req_info.month.is_some_and(|m| !(1..=12).contains(&m)) {
+        return Err(Status::invalid_argument("month must be between 1 and 12"));
+    }
+    let color = match non_empty(&req_info.color) {
+        Some(color) => Some(color_filter(&color, req_info.color_tolerance)?),
+        None => None,
+    };
+
+    Ok(PhotoFilter {
+        theme: non_empty(&req_info.theme),
+        ratio: non_empty(&req_info.ratio),
+        album,
+        archived: req_info.archived.unwrap_or(false),
+        keyword: non_empty(&req_info.keyword),
+        year: req_info.year,
+        month: req_info.month,
+        color,
+    })
+}
+
 pub mod model {
     use db_storage::models::{
-        UserUpload,
-        user_photos::{FilterableProperties, UserPhoto},
+        DescriptorEdits, GalleryEmbeddings, NearestQuery, NewUpload, UploadRejection, UserUpload,
+        albums::Album,
+        duplicates::DuplicateGroup,
+        map::{BoundingBox, MapCluster},
+        renditions::Rendition,
+        share_links::{ShareLink, ShareTarget},
+        stacks::PhotoStack,
+        storage_cleanup::PendingObjectDelete,
+        timeline::{BucketSize, Memory, TimelineBucket},
+        upload_progress::UploadProgress,
+        user_photos::{
+            FilterableProperties, HybridPhoto, PhotoCursor, PhotoDetail, PhotoFilter, PhotoPage,
+            ScoredPhoto, UserPhoto,
+        },
     };
     use derive_getters::Getters;
+    use rand::distr::{Alphanumeric, SampleString};
+    use std::collections::HashMap;
+    use uuid::Uuid;
 
     use crate::{
         bucket::{Bucket, BucketClient},
@@ -52,120 +266,961 @@ pub mod model {
             Self { conn: db, bucket }
         }
 
-        pub async fn request_upload(&self, id: UserId, upload: &FileUpload<'_>) -> Result<String> {
-            let filename = format!("feeder/{}", upload.name());
-            // Check if is duplicated
-            match UserUpload::get_by_filename(&self.conn, &filename).await {
-                Ok(_) => return Err(Box::new(Error::Duplicated)),
-                Err(_) => { /* Assume -> err means empty result */ }
-            };
+        /// Returns the upload id and the signed upload url.
+        pub async fn request_upload(
+            &self,
+            id: UserId,
+            upload: &FileUpload<'_>,
+        ) -> Result<(Uuid, String)> {
+            let requested = self.request_uploads(id, &[*upload]).await?;
+            match requested.into_iter().next() {
+                Some(Ok(accepted)) => Ok(accepted),
+                _ => Err(Box::new(Error::Duplicated)),
+            }
+        }
 
-            // Create the record
-            UserUpload::new_for_upload(&self.conn, &filename, *upload.size(), upload.hash(), &id)
-                .await?;
+        /// Creates the upload records of a batch at once.
+        /// Returns, in order, the upload id and signed url or the rejection of each file.
+        pub async fn request_uploads(
+            &self,
+            id: UserId,
+            uploads: &[FileUpload<'_>],
+        ) -> Result<Vec<std::result::Result<(Uuid, String), UploadRejection>>> {
+            let new_uploads: Vec<NewUpload> = uploads
+                .iter()
+                .map(|u| NewUpload {
+                    name: u.name(),
+                    filesize: *u.size(),
+                    filehash: u.hash(),
+                })
+                .collect();
+
+            // An upload url that expired without the upload can be requested
+            // again. A file already in the bucket waits for the feeder.
+            let filehashes: Vec<String> = uploads.iter().map(|u| u.hash().to_string()).collect();
+            let mut reissued = vec![];
+            for upload in
+                UserUpload::stale_pending(&self.conn, &id, &filehashes, STALE_UPLOAD_SECS).await?
+            {
+                if !self
+                    .bucket
+                    .object_exists(upload.filename(), Bucket::Feeder)
+                    .await?
+                {
+                    reissued.push(*upload.id());
+                }
+            }
+            let created =
+                UserUpload::new_for_uploads(&self.conn, &id, &new_uploads, &reissued).await?;
 
             // The user only needs the upload url at this point.
-            Ok(self
+            let mut requested = Vec::with_capacity(created.len());
+            for upload in created {
+                let upload = match upload {
+                    Ok(u) => u,
+                    Err(rejection) => {
+                        requested.push(Err(rejection));
+                        continue;
+                    }
+                };
+                let url = self
+                    .bucket
+                    .get_upload_signed_url(upload.filename(), Bucket::Feeder)
+                    .await?;
+                requested.push(Ok((*upload.id(), url)));
+            }
+
+            Ok(requested)
+        }
+
+        /// Returns a page of the user photos and the filtered total.
+        /// Only the photos of the page get a signed url.
+        pub async fn get(
+            &self,
+            id: UserId,
+            filter: &PhotoFilter,
+            after: Option<&PhotoCursor>,
+            size: i64,
+        ) -> Result<(PhotoPage, i64)> {
+            let mut page = UserPhoto::get_photos(&self.conn, &id, filter, after, size).await?;
+            let count = UserPhoto::count_photos(&self.conn, &id, filter).await?;
+
+            self.sign_photos(page.photos_mut().iter_mut()).await;
+
+            Ok((page, count))
+        }
+
+        /// Ranks the user photos by keywords and by the CLIP vector of the same text.
+        pub async fn search(
+            &self,
+            id: UserId,
+            text: &str,
+            embedding: Vec<f32>,
+            query: &NearestQuery,
+        ) -> Result<Vec<HybridPhoto>> {
+            let mut scored =
+                UserPhoto::hybrid_search(&self.conn, &id, text, embedding, query).await?;
+
+            self.sign_photos(scored.iter_mut().map(HybridPhoto::photo_mut))
+                .await;
+
+            Ok(scored)
+        }
+
+        /// Photos close to the given gallery item. None when the item is not found.
+        pub async fn similar(
+            &self,
+            id: UserId,
+            gallery_id: &Uuid,
+            query: &NearestQuery,
+        ) -> Result<Option<Vec<ScoredPhoto>>> {
+            let mut scored =
+                match UserPhoto::find_similar(&self.conn, &id, gallery_id, query).await? {
+                    Some(s) => s,
+                    None => return Ok(None),
+                };
+
+            self.sign_photos(scored.iter_mut().map(ScoredPhoto::photo_mut))
+                .await;
+
+            Ok(Some(scored))
+        }
+
+        /// Photo details with signed thumbnail and original urls.
+        /// None when the photo is not found.
+        pub async fn detail(
+            &self,
+            id: UserId,
+            gallery_id: &Uuid,
+        ) -> Result<Option<(PhotoDetail, String)>> {
+            let mut detail = match PhotoDetail::get_for_user(&self.conn, &id, gallery_id).await? {
+                Some(d) => d,
+                None => return Ok(None),
+            };
+
+            // Not processed yet, the original still waits in the feeder bucket.
+            let original_bucket = match detail.thumbnail_path() {
+                Some(_) => Bucket::Ragged,
+                None => Bucket::Feeder,
+            };
+            let original_url = self
                 .bucket
-                .get_upload_signed_url(&filename, Bucket::Feeder)
-                .await?)
-        }
-
-        pub async fn get(&self, id: UserId) -> Result<(Vec<UserPhoto>, i64)> {
-            let mut user_photos = UserPhoto::get_photos(&self.conn, &id).await?;
-            let count = UserPhoto::count_photos(&self.conn, &id).await?;
-
-            for photo in user_photos.iter_mut() {
-                match photo.thumbnail_path() {
-                    Some(url) => {
-                        match self
-                            .bucket
-                            .get_download_signed_url(url, Bucket::Ragged)
-                            .await
-                        {
-                            Ok(url) => photo.set_signed_url(url),
-                            Err(e) => log::error!("{e:?}"),
-                        };
+                .get_original_signed_url(detail.path(), original_bucket, detail.download_name())
+                .await?;
+
+            if let Some(path) = detail.thumbnail_path() {
+                match self
+                    .bucket
+                    .get_download_signed_url(path, Bucket::Ragged)
+                    .await
+                {
+                    Ok(url) => detail.set_signed_url(url),
+                    Err(e) => log::error!("{e:?}"),
+                };
+            }
+
+            Ok(Some((detail, original_url)))
+        }
+
+        /// Records the user edits and returns the updated details.
+        /// None when the photo is not found or not processed yet.
+        pub async fn update_descriptors(
+            &self,
+            id: UserId,
+            gallery_id: &Uuid,
+            edits: &DescriptorEdits,
+        ) -> Result<Option<(PhotoDetail, String)>> {
+            let updated =
+                GalleryEmbeddings::link_user_descriptors(&self.conn, &id, gallery_id, edits)
+                    .await?;
+            if !updated {
+                return Ok(None);
+            }
+
+            self.detail(id, gallery_id).await
+        }
+
+        /// Deletes the photo of the user. False when it is not found.
+        pub async fn delete(&self, id: UserId, gallery_id: &Uuid) -> Result<bool> {
+            let pending =
+                match PendingObjectDelete::delete_photo(&self.conn, &id, gallery_id).await? {
+                    Some(p) => p,
+                    None => return Ok(false),
+                };
+
+            self.purge_objects(pending).await;
+
+            Ok(true)
+        }
+
+        /// Near-duplicate groups of the user with signed thumbnail urls.
+        pub async fn duplicates(
+            &self,
+            id: UserId,
+            max_distance: i32,
+        ) -> Result<Vec<DuplicateGroup>> {
+            let mut groups = DuplicateGroup::find_for_user(&self.conn, &id, max_distance).await?;
+
+            self.sign_photos(
+                groups
+                    .iter_mut()
+                    .flat_map(|group| group.photos_mut().iter_mut())
+                    .map(|photo| photo.photo_mut()),
+            )
+            .await;
+
+            Ok(groups)
+        }
+
+        /// Archives the photos other than `keep`.
+        /// None when `keep` is not found, otherwise the number of photos archived.
+        pub async fn archive_duplicates(
+            &self,
+            id: UserId,
+            keep: &Uuid,
+            gallery_ids: &[Uuid],
+        ) -> Result<Option<u64>> {
+            Ok(DuplicateGroup::archive(&self.conn, &id, keep, gallery_ids).await?)
+        }
+
+        /// Deletes the photos other than `keep`, the same as `delete` does.
+        /// None when `keep` is not found, otherwise the number of photos deleted.
+        pub async fn delete_duplicates(
+            &self,
+            id: UserId,
+            keep: &Uuid,
+            gallery_ids: &[Uuid],
+        ) -> Result<Option<u64>> {
+            // The kept photo must exist, the group is not removed entirely.
+            if PhotoDetail::get_for_user(&self.conn, &id, keep)
+                .await?
+                .is_none()
+            {
+                return Ok(None);
+            }
+
+            let mut deleted = 0;
+            for gallery_id in gallery_ids.iter().filter(|g| *g != keep) {
+                if let Some(pending) =
+                    PendingObjectDelete::delete_photo(&self.conn, &id, gallery_id).await?
+                {
+                    self.purge_objects(pending).await;
+                    deleted += 1;
+                }
+            }
+
+            Ok(Some(deleted))
+        }
+
+        pub async fn timeline(
+            &self,
+            id: UserId,
+            size: BucketSize,
+            utc_offset_minutes: i32,
+            year: Option<i32>,
+        ) -> Result<Vec<TimelineBucket>> {
+            Ok(
+                TimelineBucket::list_for_user(&self.conn, &id, size, utc_offset_minutes, year)
+                    .await?,
+            )
+        }
+
+        /// Photos captured on `today` in earlier years with signed thumbnail urls.
+        pub async fn memories(
+            &self,
+            id: UserId,
+            today: time::Date,
+            utc_offset_minutes: i32,
+            per_year: i64,
+        ) -> Result<Vec<Memory>> {
+            let mut memories =
+                Memory::on_this_day(&self.conn, &id, today, utc_offset_minutes, per_year).await?;
+
+            self.sign_photos(
+                memories
+                    .iter_mut()
+                    .flat_map(|memory| memory.photos_mut().iter_mut()),
+            )
+            .await;
+
+            Ok(memories)
+        }
+
+        /// Map clusters of the user with signed thumbnail urls.
+        pub async fn map_clusters(
+            &self,
+            id: UserId,
+            bbox: &BoundingBox,
+            cell_degrees: f64,
+        ) -> Result<Vec<MapCluster>> {
+            let mut clusters = MapCluster::list_for_user(
+                &self.conn,
+                &id,
+                bbox,
+                cell_degrees,
+                super::MAX_MAP_CLUSTERS,
+            )
+            .await?;
+
+            for cluster in clusters.iter_mut() {
+                if let Some(path) = cluster.thumbnail_path() {
+                    match self
+                        .bucket
+                        .get_download_signed_url(path, Bucket::Ragged)
+                        .await
+                    {
+                        Ok(url) => cluster.set_signed_url(url),
+                        Err(e) => log::error!("{e:?}"),
+                    };
+                }
+            }
+
+            Ok(clusters)
+        }
+
+        /// Signed photos of the stack, empty when it is not found.
+        pub async fn stack_photos(&self, id: UserId, stack_id: &Uuid) -> Result<Vec<UserPhoto>> {
+            let mut photos = PhotoStack::photos(&self.conn, &id, stack_id).await?;
+            self.sign_photos(photos.iter_mut()).await;
+
+            Ok(photos)
+        }
+
+        /// False when the stack is not found or the photo is not in it.
+        pub async fn set_stack_cover(
+            &self,
+            id: UserId,
+            stack_id: &Uuid,
+            gallery_id: &Uuid,
+        ) -> Result<bool> {
+            Ok(PhotoStack::set_cover(&self.conn, &id, stack_id, gallery_id).await?)
+        }
+
+        /// Retries the bucket deletes that failed before.
+        pub async fn retry_pending_deletes(&self, batch: i64) -> Result<()> {
+            let pending = PendingObjectDelete::list_pending(&self.conn, batch).await?;
+            if !pending.is_empty() {
+                log::info!("Retrying {} pending object deletes", pending.len());
+            }
+            self.purge_objects(pending).await;
+
+            Ok(())
+        }
+
+        /// Removes the objects from the buckets. The failed ones stay pending.
+        async fn purge_objects(&self, pending: Vec<PendingObjectDelete>) {
+            for mut object in pending {
+                let deleted = self
+                    .bucket
+                    .delete_object_with_retry(object.path(), object.bucket())
+                    .await;
+
+                let recorded = match deleted {
+                    Ok(_) => object.done(&self.conn).await,
+                    Err(e) => {
+                        log::error!(
+                            "Failed to delete {}/{}, attempts {}. {e}",
+                            object.bucket(),
+                            object.path(),
+                            object.attempts() + 1
+                        );
+                        object.failed(&self.conn, &e).await
                     }
-                    _ => (),
                 };
+                if let Err(e) = recorded {
+                    log::error!("{e:?}");
+                }
+            }
+        }
+
+        /// Albums of the user with a signed cover url.
+        pub async fn albums(&self, id: UserId) -> Result<Vec<Album>> {
+            let mut albums = Album::list_for_user(&self.conn, &id).await?;
+
+            for album in albums.iter_mut() {
+                if let Some(path) = album.cover_path() {
+                    match self
+                        .bucket
+                        .get_download_signed_url(path, Bucket::Ragged)
+                        .await
+                    {
+                        Ok(url) => album.set_signed_url(url),
+                        Err(e) => log::error!("{e:?}"),
+                    };
+                }
+            }
+
+            Ok(albums)
+        }
+
+        pub async fn create_album(&self, id: UserId, name: &str) -> Result<Album> {
+            Ok(Album::create(&self.conn, &id, name).await?)
+        }
+
+        /// False when the album is not found.
+        pub async fn rename_album(&self, id: UserId, album_id: &Uuid, name: &str) -> Result<bool> {
+            Ok(Album::rename(&self.conn, &id, album_id, name).await?)
+        }
+
+        /// False when the album is not found.
+        pub async fn delete_album(&self, id: UserId, album_id: &Uuid) -> Result<bool> {
+            Ok(Album::delete(&self.conn, &id, album_id).await?)
+        }
+
+        /// False when an album is not found or repeated.
+        pub async fn reorder_albums(&self, id: UserId, album_ids: &[Uuid]) -> Result<bool> {
+            Ok(Album::reorder(&self.conn, &id, album_ids).await?)
+        }
+
+        /// False when the album is not found or the photo is not in it.
+        pub async fn set_album_cover(
+            &self,
+            id: UserId,
+            album_id: &Uuid,
+            gallery_id: &Uuid,
+        ) -> Result<bool> {
+            Ok(Album::set_cover(&self.conn, &id, album_id, gallery_id).await?)
+        }
+
+        /// Photos added. None when the album is not found.
+        pub async fn add_to_album(
+            &self,
+            id: UserId,
+            album_id: &Uuid,
+            gallery_ids: &[Uuid],
+        ) -> Result<Option<u64>> {
+            Ok(Album::add_photos(&self.conn, &id, album_id, gallery_ids).await?)
+        }
+
+        /// Photos removed. None when the album is not found.
+        pub async fn remove_from_album(
+            &self,
+            id: UserId,
+            album_id: &Uuid,
+            gallery_ids: &[Uuid],
+        ) -> Result<Option<u64>> {
+            Ok(Album::remove_photos(&self.conn, &id, album_id, gallery_ids).await?)
+        }
+
+        /// None when the photo or album is not found.
+        pub async fn share(
+            &self,
+            id: UserId,
+            target: &ShareTarget,
+            expires_at: time::OffsetDateTime,
+            max_views: Option<i32>,
+        ) -> Result<Option<ShareLink>> {
+            let token = Alphanumeric.sample_string(&mut rand::rng(), super::SHARE_TOKEN_LEN);
+            Ok(ShareLink::create(&self.conn, &id, &token, target, expires_at, max_views).await?)
+        }
+
+        pub async fn share_links(&self, id: UserId) -> Result<Vec<ShareLink>> {
+            Ok(ShareLink::list_for_user(&self.conn, &id).await?)
+        }
+
+        /// False when the link is not found or already revoked.
+        pub async fn revoke_share(&self, id: UserId, link_id: &Uuid) -> Result<bool> {
+            Ok(ShareLink::revoke(&self.conn, &id, link_id).await?)
+        }
+
+        /// Feeder progress of the user uploads, see `UploadProgress::list_for_user`.
+        pub async fn upload_progress(
+            &self,
+            id: &UserId,
+            upload_ids: &[Uuid],
+            since: Option<time::OffsetDateTime>,
+        ) -> Result<Vec<UploadProgress>> {
+            Ok(UploadProgress::list_for_user(&self.conn, id, upload_ids, since).await?)
+        }
+
+        /// Signs the thumbnails and attaches the signed renditions, with a
+        /// single renditions query. A failure leaves the photos without renditions.
+        async fn sign_photos<'p>(&self, photos: impl IntoIterator<Item = &'p mut UserPhoto>) {
+            let mut photos: Vec<&mut UserPhoto> = photos.into_iter().collect();
+            let ids: Vec<Uuid> = photos.iter().map(|photo| *photo.id()).collect();
+            let mut renditions: HashMap<Uuid, Vec<Rendition>> = HashMap::new();
+            match Rendition::list_for_galleries(&self.conn, &ids).await {
+                Ok(found) => {
+                    for rendition in found {
+                        renditions
+                            .entry(*rendition.gallery_id())
+                            .or_default()
+                            .push(rendition);
+                    }
+                }
+                Err(e) => log::error!("{e:?}"),
+            };
+
+            for photo in photos.iter_mut() {
+                self.sign_thumbnail(photo).await;
+
+                let mut signed = vec![];
+                for mut rendition in renditions.remove(photo.id()).unwrap_or_default() {
+                    match self
+                        .bucket
+                        .get_download_signed_url(rendition.path(), Bucket::Ragged)
+                        .await
+                    {
+                        Ok(url) => {
+                            rendition.set_signed_url(url);
+                            signed.push(rendition);
+                        }
+                        Err(e) => log::error!("{e:?}"),
+                    };
+                }
+                photo.set_renditions(signed);
             }
+        }
 
-            Ok((user_photos, count))
+        /// Replaces the thumbnail bucket path with a signed download url.
+        async fn sign_thumbnail(&self, photo: &mut UserPhoto) {
+            match photo.thumbnail_path() {
+                Some(url) => {
+                    match self
+                        .bucket
+                        .get_download_signed_url(url, Bucket::Ragged)
+                        .await
+                    {
+                        Ok(url) => photo.set_signed_url(url),
+                        Err(e) => log::error!("{e:?}"),
+                    };
+                }
+                _ => (),
+            };
         }
 
         pub async fn filters(
             &self,
             id: UserId,
+            filter: &PhotoFilter,
         ) -> Result<db_storage::models::user_photos::FilterableProperties> {
-            Ok(FilterableProperties::get_for_user(&self.conn, id.into()).await?)
+            Ok(
+                FilterableProperties::get_for_user(&self.conn, &id, filter, super::TOP_KEYWORDS)
+                    .await?,
+            )
         }
     }
 }
 
-impl From<Vec<db_storage::models::user_photos::UserPhoto>> for GalleryImagesResponse {
-    fn from(value: Vec<db_storage::models::user_photos::UserPhoto>) -> Self {
+impl From<&db_storage::models::user_photos::UserPhoto> for GalleryImage {
+    fn from(f: &db_storage::models::user_photos::UserPhoto) -> Self {
+        GalleryImage {
+            img_url: f.thumbnail_path().as_ref().map_or("", |f| f).to_string(),
+            aria_text: f.img_aria().as_ref().map_or("", |f| f).to_string(),
+            aspect: f.thumbnail_ratio().as_ref().map_or("", |f| f).to_string(),
+            theme: f.theme().as_ref().map_or("", |f| f).to_string(),
+            alt_text: f.img_alt().as_ref().map_or("", |f| f).to_string(),
+            id: f.id().to_string(),
+            sources: f.renditions().iter().map(ImageSource::from).collect(),
+            stack_id: f.stack_id().map(|id| id.to_string()),
+            stack_size: *f.stack_size() as i32,
+            blur_hash: f.blurhash().clone(),
+            width: f.thumbnail_width().unwrap_or_default(),
+            height: f.thumbnail_height().unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&db_storage::models::renditions::Rendition> for ImageSource {
+    fn from(f: &db_storage::models::renditions::Rendition) -> Self {
+        ImageSource {
+            url: f.path().clone(),
+            width: *f.width(),
+            height: *f.height(),
+            mime_type: f.format().mime_type().to_string(),
+            animated: f.format().is_animated(),
+        }
+    }
+}
+
+impl From<Vec<db_storage::models::user_photos::ScoredPhoto>> for SearchGalleryResponse {
+    fn from(value: Vec<db_storage::models::user_photos::ScoredPhoto>) -> Self {
         Self {
             images: value
                 .iter()
-                .map(|f| GalleryImage {
-                    img_url: f.thumbnail_path().as_ref().map_or("", |f| f).to_string(),
-                    aria_text: f.img_aria().as_ref().map_or("", |f| f).to_string(),
-                    aspect: f.thumbnail_ratio().as_ref().map_or("", |f| f).to_string(),
-                    theme: f.theme().as_ref().map_or("", |f| f).to_string(),
-                    alt_text: f.img_alt().as_ref().map_or("", |f| f).to_string(),
+                .map(|f| ScoredGalleryImage {
+                    image: Some(f.photo().into()),
+                    similarity: f.similarity(),
+                    distance: *f.distance(),
+                    matched: MatchSignal::Vector as i32,
+                    score: f.similarity(),
                 })
                 .collect(),
-            count: 0,
         }
     }
 }
 
-#[derive(Debug)]
-pub struct GalleryService<'a> {
-    conn: db_storage::DbConn,
-    bucket: BucketClient<'a>,
-    session_middleware: SessionValidator,
+impl From<&db_storage::models::user_photos::FacetCount> for FacetCount {
+    fn from(f: &db_storage::models::user_photos::FacetCount) -> Self {
+        FacetCount {
+            value: f.value().to_string(),
+            count: *f.count() as i32,
+        }
+    }
 }
 
-impl<'a> GalleryService<'a> {
-    pub fn new(
-        conn: db_storage::DbConn,
-        bucket: BucketClient<'a>,
-        session_middleware: SessionValidator,
-    ) -> GalleryService<'a> {
-        Self {
-            conn,
-            bucket,
-            session_middleware,
+impl From<&db_storage::models::user_photos::FilterableProperties> for FilterOptionResponse {
+    fn from(f: &db_storage::models::user_photos::FilterableProperties) -> Self {
+        FilterOptionResponse {
+            aspects: f.aspects().iter().map(|a| a.value().to_string()).collect(),
+            themes: f.themes().iter().map(|t| t.value().to_string()).collect(),
+            aspect_counts: f.aspects().iter().map(FacetCount::from).collect(),
+            theme_counts: f.themes().iter().map(FacetCount::from).collect(),
+            keywords: f.keywords().iter().map(FacetCount::from).collect(),
+            colors: f.colors().iter().map(FacetCount::from).collect(),
+            upload_months: f
+                .months()
+                .iter()
+                .map(|m| MonthCount {
+                    year: *m.year(),
+                    month: *m.month(),
+                    count: *m.count() as i32,
+                })
+                .collect(),
         }
     }
 }
 
-#[tonic::async_trait]
-impl<'a> GalleryView for GalleryService<'static> {
-    async fn upload_image(
-        &self,
-        request: Request<UploadImageRequest>,
-    ) -> std::result::Result<Response<SignedLinkResponse>, Status> {
-        let user_id = match self.session_middleware.get_user(&request).await {
-            Ok(u) => u,
-            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
-        };
-        // let user_id: UserId = Uuid::nil();
-        let req_info = request.get_ref();
-        let file_info = FileUpload::new(&req_info.filename, &req_info.filehash, req_info.filesize);
+impl From<&db_storage::models::duplicates::DuplicatePhoto> for DuplicatePhoto {
+    fn from(f: &db_storage::models::duplicates::DuplicatePhoto) -> Self {
+        DuplicatePhoto {
+            image: Some(f.photo().into()),
+            original: dimensions(f.original_width(), f.original_height()),
+            filesize: *f.filesize(),
+        }
+    }
+}
 
-        let uploadurl =
-            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
-                .request_upload(user_id, &file_info)
-                .await;
+fn epoch_millis(datetime: &time::OffsetDateTime) -> u64 {
+    (datetime.unix_timestamp_nanos() / 1_000_000) as u64
+}
 
-        match uploadurl {
-            Ok(bucket_link) => Ok(Response::new(SignedLinkResponse { bucket_link })),
-            Err(_) => Ok(Response::new(SignedLinkResponse {
-                bucket_link: "None".to_string(),
-            })),
+impl From<&db_storage::models::albums::Album> for Album {
+    fn from(f: &db_storage::models::albums::Album) -> Self {
+        Album {
+            id: f.id().to_string(),
+            name: f.name().to_string(),
+            photo_count: *f.photo_count() as i32,
+            cover_id: f.cover_id().map_or(String::new(), |c| c.to_string()),
+            cover_url: f.cover_path().as_ref().map_or("", |f| f).to_string(),
+            created_at: epoch_millis(f.created_at()),
+            updated_at: epoch_millis(f.updated_at()),
+        }
+    }
+}
+
+fn share_link(link: &db_storage::models::share_links::ShareLink, public_url: &str) -> ShareLink {
+    ShareLink {
+        id: link.id().to_string(),
+        url: format!("{public_url}/share/{}", link.token()),
+        photo_id: link.gallery_id().map_or(String::new(), |g| g.to_string()),
+        album_id: link.album_id().map_or(String::new(), |a| a.to_string()),
+        max_views: link.max_views().map(|v| v as u32),
+        views: *link.views() as u32,
+        revoked: link.revoked_at().is_some(),
+        expires_at: epoch_millis(link.expires_at()),
+        created_at: epoch_millis(link.created_at()),
+    }
+}
+
+impl From<db_storage::models::upload_progress::ProcessingStatus> for ProcessingStatus {
+    fn from(value: db_storage::models::upload_progress::ProcessingStatus) -> Self {
+        use db_storage::models::upload_progress::ProcessingStatus as Db;
+        match value {
+            Db::Pending => ProcessingStatus::Pending,
+            Db::Uploaded => ProcessingStatus::Uploaded,
+            Db::ThumbnailReady => ProcessingStatus::ThumbnailReady,
+            Db::Embedded => ProcessingStatus::Embedded,
+            Db::Described => ProcessingStatus::Described,
+            Db::DescriptionFailed => ProcessingStatus::DescriptionFailed,
+            Db::Failed => ProcessingStatus::Failed,
+        }
+    }
+}
+
+impl From<&db_storage::models::upload_progress::UploadProgress> for UploadProgress {
+    fn from(f: &db_storage::models::upload_progress::UploadProgress) -> Self {
+        UploadProgress {
+            upload_id: f.upload_id().to_string(),
+            filename: f
+                .filename()
+                .strip_prefix("feeder/")
+                .unwrap_or(f.filename())
+                .to_string(),
+            status: ProcessingStatus::from(f.status()) as i32,
+            gallery_id: f.gallery_id().map_or(String::new(), |g| g.to_string()),
+            error: f.error().as_ref().map_or("", |e| e).to_string(),
+            updated_at: epoch_millis(f.updated_at()),
+        }
+    }
+}
+
+/// Sends the upload changes until the watched uploads are done,
+/// the client disconnects or the watch times out.
+async fn watch_uploads(
+    user_gallery: model::UserGallery<'static>,
+    user_id: UserId,
+    upload_ids: Vec<Uuid>,
+    tx: mpsc::Sender<std::result::Result<UploadProgress, Status>>,
+) {
+    let deadline = tokio::time::Instant::now() + WATCH_TIMEOUT;
+    let mut interval = tokio::time::interval(WATCH_POLL_INTERVAL);
+    // Last state sent of each upload. Polls overlap, this drops the repeated ones.
+    let mut sent: std::collections::HashMap<
+        Uuid,
+        (
+            db_storage::models::upload_progress::ProcessingStatus,
+            time::OffsetDateTime,
+        ),
+    > = std::collections::HashMap::new();
+    let mut since = None;
+
+    while tokio::time::Instant::now() < deadline && !tx.is_closed() {
+        interval.tick().await;
+        let polled_at = time::OffsetDateTime::now_utc();
+
+        let progress = user_gallery
+            .upload_progress(&user_id, &upload_ids, since)
+            .await;
+        // The error is not Send, it is dropped before the next await.
+        let progress = match progress {
+            Ok(p) => Some(p),
+            Err(e) => {
+                log::error!("{e:?}");
+                None
+            }
+        };
+        let Some(progress) = progress else {
+            let _ = tx
+                .send(Err(Status::internal("Failed to get upload progress")))
+                .await;
+            return;
+        };
+
+        for upload in progress.iter() {
+            let state = (upload.status(), *upload.updated_at());
+            if sent.get(upload.upload_id()) == Some(&state) {
+                continue;
+            }
+            sent.insert(*upload.upload_id(), state);
+            if tx.send(Ok(upload.into())).await.is_err() {
+                return;
+            }
+        }
+        since = Some(polled_at - WATCH_OVERLAP);
+
+        let done = !upload_ids.is_empty()
+            && upload_ids
+                .iter()
+                .all(|id| sent.get(id).is_some_and(|(status, _)| status.is_final()));
+        if done {
+            return;
+        }
+    }
+}
+
+/// Upload entries the feeder can not process.
+/// The feeder checks the uploaded bytes against the SHA-256 `filehash`.
+fn is_valid_upload(file: &UploadImageRequest) -> bool {
+    !file.filename.trim().is_empty()
+        && file.filesize > 0
+        && file.filehash.len() == 64
+        && file.filehash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl From<db_storage::models::UploadRejection> for UploadRejection {
+    fn from(value: db_storage::models::UploadRejection) -> Self {
+        match value {
+            db_storage::models::UploadRejection::Duplicated => UploadRejection::Duplicated,
+            db_storage::models::UploadRejection::RepeatedInBatch => {
+                UploadRejection::RepeatedInRequest
+            }
+        }
+    }
+}
+
+fn descriptor_source(edited: bool) -> DescriptorSource {
+    match edited {
+        true => DescriptorSource::User,
+        false => DescriptorSource::Ai,
+    }
+}
+
+impl TryFrom<&UpdatePhotoMetadataRequest> for DescriptorEdits {
+    type Error = Status;
+
+    fn try_from(value: &UpdatePhotoMetadataRequest) -> std::result::Result<Self, Self::Error> {
+        let mut reset = Vec::new();
+        for field in value.reset.iter() {
+            let field = match DescriptorField::try_from(*field) {
+                Ok(DescriptorField::Keywords) => db_storage::models::DescriptorField::Keywords,
+                Ok(DescriptorField::Description) => {
+                    db_storage::models::DescriptorField::Description
+                }
+                Ok(DescriptorField::Theme) => db_storage::models::DescriptorField::Theme,
+                Ok(DescriptorField::AltText) => db_storage::models::DescriptorField::ImgAlt,
+                Ok(DescriptorField::AriaText) => db_storage::models::DescriptorField::ImgAria,
+                _ => return Err(Status::invalid_argument("Unknown reset field")),
+            };
+            reset.push(field);
+        }
+
+        Ok(DescriptorEdits {
+            keywords: value.keywords.as_ref().map(|k| {
+                k.values
+                    .iter()
+                    .map(|v| v.trim().to_string())
+                    .filter(|v| !v.is_empty())
+                    .collect()
+            }),
+            description: value.description.as_ref().map(|v| v.trim().to_string()),
+            theme: value.theme.as_ref().map(|v| v.trim().to_string()),
+            img_alt: value.alt_text.as_ref().map(|v| v.trim().to_string()),
+            img_aria: value.aria_text.as_ref().map(|v| v.trim().to_string()),
+            reset,
+        })
+    }
+}
+
+fn dimensions(width: &Option<i32>, height: &Option<i32>) -> Option<Dimensions> {
+    match (width, height) {
+        (Some(width), Some(height)) => Some(Dimensions {
+            width: *width,
+            height: *height,
+        }),
+        _ => None,
+    }
+}
+
+impl From<(PhotoDetail, String)> for PhotoDetailResponse {
+    fn from(value: (PhotoDetail, String)) -> Self {
+        let (f, original_url) = value;
+        Self {
+            id: f.id().to_string(),
+            thumbnail_url: f.thumbnail_path().clone().unwrap_or_default(),
+            original_url,
+            description: f.description().clone().unwrap_or_default(),
+            keywords: f.keywords().clone().unwrap_or_default(),
+            theme: f.theme().clone().unwrap_or_default(),
+            alt_text: f.img_alt().clone().unwrap_or_default(),
+            aria_text: f.img_aria().clone().unwrap_or_default(),
+            aspect: f.thumbnail_ratio().clone().unwrap_or_default(),
+            original: dimensions(f.original_width(), f.original_height()),
+            thumbnail: dimensions(f.thumbnail_width(), f.thumbnail_height()),
+            filename: f.download_name().to_string(),
+            filesize: *f.filesize(),
+            uploaded_at: epoch_millis(f.uploaded_at()),
+            created_at: epoch_millis(f.created_at()),
+            updated_at: epoch_millis(f.updated_at()),
+            sources: Some(DescriptorSources {
+                keywords: descriptor_source(*f.keywords_edited()) as i32,
+                description: descriptor_source(*f.description_edited()) as i32,
+                theme: descriptor_source(*f.theme_edited()) as i32,
+                alt_text: descriptor_source(*f.img_alt_edited()) as i32,
+                aria_text: descriptor_source(*f.img_aria_edited()) as i32,
+            }),
+        }
+    }
+}
+
+impl From<db_storage::models::user_photos::MatchSignal> for MatchSignal {
+    fn from(value: db_storage::models::user_photos::MatchSignal) -> Self {
+        match value {
+            db_storage::models::user_photos::MatchSignal::Keyword => MatchSignal::Keyword,
+            db_storage::models::user_photos::MatchSignal::Vector => MatchSignal::Vector,
+            db_storage::models::user_photos::MatchSignal::Both => MatchSignal::Both,
+        }
+    }
+}
+
+impl From<Vec<db_storage::models::user_photos::HybridPhoto>> for SearchGalleryResponse {
+    fn from(value: Vec<db_storage::models::user_photos::HybridPhoto>) -> Self {
+        Self {
+            images: value
+                .iter()
+                .map(|f| ScoredGalleryImage {
+                    image: Some(f.photo().into()),
+                    similarity: f.similarity(),
+                    distance: *f.distance(),
+                    matched: MatchSignal::from(f.matched_by()) as i32,
+                    score: *f.score(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Vec<db_storage::models::user_photos::UserPhoto>> for GalleryImagesResponse {
+    fn from(value: Vec<db_storage::models::user_photos::UserPhoto>) -> Self {
+        Self {
+            images: value.iter().map(GalleryImage::from).collect(),
+            count: 0,
+            next_page: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GalleryService<'a> {
+    conn: db_storage::DbConn,
+    bucket: BucketClient<'a>,
+    session_middleware: SessionValidator,
+    text_embedder: TextEmbedder,
+    /// Base of the share links urls
+    share_url: &'a str,
+}
+
+impl<'a> GalleryService<'a> {
+    pub fn new(
+        conn: db_storage::DbConn,
+        bucket: BucketClient<'a>,
+        session_middleware: SessionValidator,
+        text_embedder: TextEmbedder,
+        share_url: &'a str,
+    ) -> GalleryService<'a> {
+        Self {
+            conn,
+            bucket,
+            session_middleware,
+            text_embedder,
+            share_url,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<'a> GalleryView for GalleryService<'static> {
+    async fn upload_image(
+        &self,
+        request: Request<UploadImageRequest>,
+    ) -> std::result::Result<Response<SignedLinkResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        // let user_id: UserId = Uuid::nil();
+        let req_info = request.get_ref();
+        if !is_valid_upload(req_info) {
+            return Err(Status::invalid_argument(
+                "filename, filesize and the SHA-256 filehash are required",
+            ));
+        }
+        let file_info = FileUpload::new(&req_info.filename, &req_info.filehash, req_info.filesize);
+
+        let uploadurl =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .request_upload(user_id, &file_info)
+                .await;
+
+        match uploadurl {
+            Ok((upload_id, bucket_link)) => Ok(Response::new(SignedLinkResponse {
+                bucket_link,
+                upload_id: upload_id.to_string(),
+            })),
+            Err(_) => Ok(Response::new(SignedLinkResponse {
+                bucket_link: "None".to_string(),
+                upload_id: String::new(),
+            })),
         }
     }
 
@@ -177,36 +1232,885 @@ impl<'a> GalleryView for GalleryService<'static> {
             Ok(u) => u,
             Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
         };
+        let req_info = request.get_ref();
+        let size = page_size(&req_info.size)?;
+        let after = match non_empty(&req_info.page) {
+            Some(page) => Some(
+                page.parse::<PhotoCursor>()
+                    .map_err(Status::invalid_argument)?,
+            ),
+            None => None,
+        };
+        let filter = photo_filter(req_info)?;
+
         let get_response =
             crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
-                .get(user_id)
+                .get(user_id, &filter, after.as_ref(), size)
                 .await;
 
-        let (user_photos, count) = get_response.unwrap();
+        let (page, count) = match get_response {
+            Ok(r) => r,
+            Err(e) => {
+                log::error!("{e:?}");
+                return Err(Status::internal("Failed to get gallery"));
+            }
+        };
+        let (user_photos, next) = page.into_parts();
 
         let mut gallery_results: GalleryImagesResponse = user_photos.into();
         gallery_results.count = count as i32;
+        gallery_results.next_page = next.map(|c| c.to_string());
 
         Ok(Response::new(gallery_results))
     }
 
     async fn filter_options(
         &self,
-        request: Request<EmptyRequest>,
+        request: Request<FilterGalleryRequest>,
     ) -> std::result::Result<Response<FilterOptionResponse>, Status> {
         let user_id = match self.session_middleware.get_user(&request).await {
             Ok(u) => u,
             Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
         };
+        let filter = photo_filter(request.get_ref())?;
+
         let filters =
             crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
-                .filters(user_id)
+                .filters(user_id, &filter)
+                .await;
+
+        match filters {
+            Ok(filters) => Ok(Response::new((&filters).into())),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to get filter options"))
+            }
+        }
+    }
+
+    async fn search_gallery(
+        &self,
+        request: Request<SearchGalleryRequest>,
+    ) -> std::result::Result<Response<SearchGalleryResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let search_text = req_info.search_text.trim();
+        if search_text.is_empty() {
+            return Err(Status::invalid_argument("searchText is empty"));
+        }
+        let query = nearest_query(req_info.size, req_info.min_similarity);
+
+        let embedding = match self.text_embedder.embed(search_text).await {
+            Ok(e) => e,
+            Err(e) => {
+                log::error!("{e:?}");
+                return Err(Status::internal("Failed to process searchText"));
+            }
+        };
+
+        let scored =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .search(user_id, search_text, embedding, &query)
+                .await;
+
+        match scored {
+            Ok(scored) => Ok(Response::new(scored.into())),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to search gallery"))
+            }
+        }
+    }
+
+    async fn find_similar(
+        &self,
+        request: Request<FindSimilarRequest>,
+    ) -> std::result::Result<Response<SearchGalleryResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let gallery_id = Uuid::parse_str(&req_info.id)
+            .map_err(|_| Status::invalid_argument("id is not a valid gallery id"))?;
+        let query = nearest_query(req_info.size, req_info.min_similarity);
+
+        let scored =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .similar(user_id, &gallery_id, &query)
+                .await;
+
+        match scored {
+            Ok(Some(scored)) => Ok(Response::new(scored.into())),
+            Ok(None) => Err(Status::not_found("Gallery item not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to find similar photos"))
+            }
+        }
+    }
+
+    async fn delete_photo(
+        &self,
+        request: Request<DeletePhotoRequest>,
+    ) -> std::result::Result<Response<EmptyResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let gallery_id = Uuid::parse_str(&request.get_ref().id)
+            .map_err(|_| Status::invalid_argument("id is not a valid gallery id"))?;
+
+        let deleted =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .delete(user_id, &gallery_id)
+                .await;
+
+        match deleted {
+            Ok(true) => Ok(Response::new(EmptyResponse {})),
+            Ok(false) => Err(Status::not_found("Gallery item not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to delete photo"))
+            }
+        }
+    }
+
+    async fn get_photo(
+        &self,
+        request: Request<GetPhotoRequest>,
+    ) -> std::result::Result<Response<PhotoDetailResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let gallery_id = Uuid::parse_str(&request.get_ref().id)
+            .map_err(|_| Status::invalid_argument("id is not a valid gallery id"))?;
+
+        let detail =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .detail(user_id, &gallery_id)
+                .await;
+
+        match detail {
+            Ok(Some(detail)) => Ok(Response::new(detail.into())),
+            Ok(None) => Err(Status::not_found("Gallery item not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to get photo"))
+            }
+        }
+    }
+
+    async fn update_photo_metadata(
+        &self,
+        request: Request<UpdatePhotoMetadataRequest>,
+    ) -> std::result::Result<Response<PhotoDetailResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let gallery_id = Uuid::parse_str(&req_info.id)
+            .map_err(|_| Status::invalid_argument("id is not a valid gallery id"))?;
+        let edits = DescriptorEdits::try_from(req_info)?;
+
+        let detail =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .update_descriptors(user_id, &gallery_id, &edits)
+                .await;
+
+        match detail {
+            Ok(Some(detail)) => Ok(Response::new(detail.into())),
+            Ok(None) => Err(Status::not_found("Processed gallery item not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to update photo"))
+            }
+        }
+    }
+
+    async fn list_albums(
+        &self,
+        request: Request<EmptyRequest>,
+    ) -> std::result::Result<Response<AlbumsResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+
+        let albums =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .albums(user_id)
+                .await;
+
+        match albums {
+            Ok(albums) => Ok(Response::new(AlbumsResponse {
+                albums: albums.iter().map(Album::from).collect(),
+            })),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to get albums"))
+            }
+        }
+    }
+
+    async fn create_album(
+        &self,
+        request: Request<CreateAlbumRequest>,
+    ) -> std::result::Result<Response<Album>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let name = album_name(&request.get_ref().name)?;
+
+        let album =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .create_album(user_id, &name)
+                .await;
+
+        match album {
+            Ok(album) => Ok(Response::new((&album).into())),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to create album"))
+            }
+        }
+    }
+
+    async fn rename_album(
+        &self,
+        request: Request<RenameAlbumRequest>,
+    ) -> std::result::Result<Response<EmptyResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let album_id = album_id(&req_info.id)?;
+        let name = album_name(&req_info.name)?;
+
+        let renamed =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .rename_album(user_id, &album_id, &name)
+                .await;
+
+        match renamed {
+            Ok(true) => Ok(Response::new(EmptyResponse {})),
+            Ok(false) => Err(Status::not_found("Album not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to rename album"))
+            }
+        }
+    }
+
+    async fn delete_album(
+        &self,
+        request: Request<AlbumRequest>,
+    ) -> std::result::Result<Response<EmptyResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let album_id = album_id(&request.get_ref().id)?;
+
+        let deleted =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .delete_album(user_id, &album_id)
+                .await;
+
+        match deleted {
+            Ok(true) => Ok(Response::new(EmptyResponse {})),
+            Ok(false) => Err(Status::not_found("Album not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to delete album"))
+            }
+        }
+    }
+
+    async fn reorder_albums(
+        &self,
+        request: Request<ReorderAlbumsRequest>,
+    ) -> std::result::Result<Response<EmptyResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let album_ids = request
+            .get_ref()
+            .ids
+            .iter()
+            .map(|id| album_id(id))
+            .collect::<std::result::Result<Vec<Uuid>, Status>>()?;
+
+        let reordered =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .reorder_albums(user_id, &album_ids)
+                .await;
+
+        match reordered {
+            Ok(true) => Ok(Response::new(EmptyResponse {})),
+            Ok(false) => Err(Status::invalid_argument(
+                "Albums must be unique and belong to the user",
+            )),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to reorder albums"))
+            }
+        }
+    }
+
+    async fn set_album_cover(
+        &self,
+        request: Request<AlbumCoverRequest>,
+    ) -> std::result::Result<Response<EmptyResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let album_id = album_id(&req_info.id)?;
+        let gallery_id = Uuid::parse_str(&req_info.photo_id)
+            .map_err(|_| Status::invalid_argument("photoId is not a valid gallery id"))?;
+
+        let updated =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .set_album_cover(user_id, &album_id, &gallery_id)
+                .await;
+
+        match updated {
+            Ok(true) => Ok(Response::new(EmptyResponse {})),
+            Ok(false) => Err(Status::not_found("Photo not found in the album")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to set album cover"))
+            }
+        }
+    }
+
+    async fn add_album_photos(
+        &self,
+        request: Request<AlbumPhotosRequest>,
+    ) -> std::result::Result<Response<AlbumPhotosResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let album_id = album_id(&req_info.id)?;
+        let gallery_ids = gallery_ids(&req_info.photo_ids)?;
+
+        let added =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .add_to_album(user_id, &album_id, &gallery_ids)
+                .await;
+
+        match added {
+            Ok(Some(changed)) => Ok(Response::new(AlbumPhotosResponse {
+                changed: changed as i32,
+            })),
+            Ok(None) => Err(Status::not_found("Album not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to add album photos"))
+            }
+        }
+    }
+
+    async fn remove_album_photos(
+        &self,
+        request: Request<AlbumPhotosRequest>,
+    ) -> std::result::Result<Response<AlbumPhotosResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let album_id = album_id(&req_info.id)?;
+        let gallery_ids = gallery_ids(&req_info.photo_ids)?;
+
+        let removed =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .remove_from_album(user_id, &album_id, &gallery_ids)
+                .await;
+
+        match removed {
+            Ok(Some(changed)) => Ok(Response::new(AlbumPhotosResponse {
+                changed: changed as i32,
+            })),
+            Ok(None) => Err(Status::not_found("Album not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to remove album photos"))
+            }
+        }
+    }
+
+    async fn create_share_link(
+        &self,
+        request: Request<CreateShareLinkRequest>,
+    ) -> std::result::Result<Response<ShareLink>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let target = match &req_info.target {
+            Some(create_share_link_request::Target::PhotoId(id)) => ShareTarget::Photo(
+                Uuid::parse_str(id)
+                    .map_err(|_| Status::invalid_argument("photoId is not a valid gallery id"))?,
+            ),
+            Some(create_share_link_request::Target::AlbumId(id)) => {
+                ShareTarget::Album(album_id(id)?)
+            }
+            None => return Err(Status::invalid_argument("photoId or albumId is required")),
+        };
+        let hours = req_info
+            .expires_in_hours
+            .unwrap_or(DEFAULT_SHARE_HOURS)
+            .clamp(1, MAX_SHARE_HOURS);
+        let expires_at = time::OffsetDateTime::now_utc() + time::Duration::hours(hours as i64);
+        let max_views = match req_info.max_views {
+            Some(0) => return Err(Status::invalid_argument("maxViews must be positive")),
+            Some(views) => Some(views.min(i32::MAX as u32) as i32),
+            None => None,
+        };
+
+        let link =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .share(user_id, &target, expires_at, max_views)
+                .await;
+
+        match link {
+            Ok(Some(link)) => Ok(Response::new(share_link(&link, self.share_url))),
+            Ok(None) => Err(Status::not_found("Photo or album not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to create share link"))
+            }
+        }
+    }
+
+    async fn list_share_links(
+        &self,
+        request: Request<EmptyRequest>,
+    ) -> std::result::Result<Response<ShareLinksResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+
+        let links =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .share_links(user_id)
+                .await;
+
+        match links {
+            Ok(links) => Ok(Response::new(ShareLinksResponse {
+                links: links
+                    .iter()
+                    .map(|l| share_link(l, self.share_url))
+                    .collect(),
+            })),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to get share links"))
+            }
+        }
+    }
+
+    async fn revoke_share_link(
+        &self,
+        request: Request<ShareLinkRequest>,
+    ) -> std::result::Result<Response<EmptyResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let link_id = Uuid::parse_str(&request.get_ref().id)
+            .map_err(|_| Status::invalid_argument("id is not a valid share link id"))?;
+
+        let revoked =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .revoke_share(user_id, &link_id)
+                .await;
+
+        match revoked {
+            Ok(true) => Ok(Response::new(EmptyResponse {})),
+            Ok(false) => Err(Status::not_found("Share link not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to revoke share link"))
+            }
+        }
+    }
+
+    type WatchUploadsStream = ReceiverStream<std::result::Result<UploadProgress, Status>>;
+
+    async fn watch_uploads(
+        &self,
+        request: Request<WatchUploadsRequest>,
+    ) -> std::result::Result<Response<Self::WatchUploadsStream>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let upload_ids = request
+            .get_ref()
+            .upload_ids
+            .iter()
+            .map(|id| {
+                Uuid::parse_str(id)
+                    .map_err(|_| Status::invalid_argument(format!("{id} is not a valid upload id")))
+            })
+            .collect::<std::result::Result<Vec<Uuid>, Status>>()?;
+
+        let (tx, rx) = mpsc::channel(16);
+        let user_gallery =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone());
+        tokio::spawn(watch_uploads(user_gallery, user_id, upload_ids, tx));
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn request_uploads(
+        &self,
+        request: Request<RequestUploadsRequest>,
+    ) -> std::result::Result<Response<RequestUploadsResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let files = &request.get_ref().files;
+        if files.len() > MAX_BATCH_UPLOADS {
+            return Err(Status::invalid_argument(format!(
+                "Up to {MAX_BATCH_UPLOADS} files per request"
+            )));
+        }
+        let valid_files: Vec<FileUpload> = files
+            .iter()
+            .filter(|f| is_valid_upload(f))
+            .map(|f| FileUpload::new(&f.filename, &f.filehash, f.filesize))
+            .collect();
+
+        let requested =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .request_uploads(user_id, &valid_files)
+                .await;
+
+        let mut requested = match requested {
+            Ok(r) => r.into_iter(),
+            Err(e) => {
+                log::error!("{e:?}");
+                return Err(Status::internal("Failed to request uploads"));
+            }
+        };
+
+        // Invalid files were not requested, they keep their place in the response.
+        let uploads = files
+            .iter()
+            .map(|file| {
+                let result = match is_valid_upload(file).then(|| requested.next()).flatten() {
+                    Some(Ok((upload_id, bucket_link))) => {
+                        upload_slot::Result::Accepted(SignedLinkResponse {
+                            bucket_link,
+                            upload_id: upload_id.to_string(),
+                        })
+                    }
+                    Some(Err(rejection)) => {
+                        upload_slot::Result::Rejected(UploadRejection::from(rejection) as i32)
+                    }
+                    None => upload_slot::Result::Rejected(UploadRejection::Invalid as i32),
+                };
+                UploadSlot {
+                    filename: file.filename.clone(),
+                    result: Some(result),
+                }
+            })
+            .collect();
+
+        Ok(Response::new(RequestUploadsResponse { uploads }))
+    }
+
+    async fn list_duplicates(
+        &self,
+        request: Request<ListDuplicatesRequest>,
+    ) -> std::result::Result<Response<DuplicateGroupsResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let max_distance = request
+            .get_ref()
+            .max_distance
+            .unwrap_or(db_storage::models::duplicates::DEFAULT_MAX_DISTANCE);
+        if !(0..=MAX_DUPLICATE_DISTANCE).contains(&max_distance) {
+            return Err(Status::invalid_argument(format!(
+                "maxDistance must be between 0 and {MAX_DUPLICATE_DISTANCE}"
+            )));
+        }
+
+        let groups =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .duplicates(user_id, max_distance)
                 .await;
 
-        let filters = filters.unwrap();
-        Ok(Response::new(FilterOptionResponse {
-            aspects: filters.aspects().clone(),
-            themes: filters.themes().clone(),
-        }))
+        match groups {
+            Ok(groups) => Ok(Response::new(DuplicateGroupsResponse {
+                groups: groups
+                    .iter()
+                    .map(|g| DuplicateGroup {
+                        photos: g.photos().iter().map(DuplicatePhoto::from).collect(),
+                    })
+                    .collect(),
+            })),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to list duplicates"))
+            }
+        }
+    }
+
+    async fn resolve_duplicates(
+        &self,
+        request: Request<ResolveDuplicatesRequest>,
+    ) -> std::result::Result<Response<ResolveDuplicatesResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let keep = Uuid::parse_str(&req_info.keep_id)
+            .map_err(|_| Status::invalid_argument("keepId is not a valid gallery id"))?;
+        let remove = gallery_ids(&req_info.remove_ids)?;
+        let resolution = DuplicateResolution::try_from(req_info.resolution)
+            .map_err(|_| Status::invalid_argument("Unknown resolution"))?;
+
+        let user_gallery =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone());
+        let resolved = match resolution {
+            DuplicateResolution::Archive => {
+                user_gallery
+                    .archive_duplicates(user_id, &keep, &remove)
+                    .await
+            }
+            DuplicateResolution::Delete => {
+                user_gallery
+                    .delete_duplicates(user_id, &keep, &remove)
+                    .await
+            }
+        };
+
+        match resolved {
+            Ok(Some(resolved)) => Ok(Response::new(ResolveDuplicatesResponse {
+                resolved: resolved as i32,
+            })),
+            Ok(None) => Err(Status::not_found("Gallery item not found")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to resolve duplicates"))
+            }
+        }
+    }
+
+    async fn timeline(
+        &self,
+        request: Request<TimelineRequest>,
+    ) -> std::result::Result<Response<TimelineResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let size = match TimelineBucketSize::try_from(req_info.bucket_size) {
+            Ok(TimelineBucketSize::Day) => BucketSize::Day,
+            Ok(TimelineBucketSize::Month) => BucketSize::Month,
+            Ok(TimelineBucketSize::Year) => BucketSize::Year,
+            Err(_) => return Err(Status::invalid_argument("Unknown bucketSize")),
+        };
+        let utc_offset = utc_offset_minutes(req_info.utc_offset_minutes)?;
+
+        let buckets =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .timeline(user_id, size, utc_offset, req_info.year)
+                .await;
+
+        match buckets {
+            Ok(buckets) => Ok(Response::new(TimelineResponse {
+                buckets: buckets
+                    .iter()
+                    .map(|b| TimelineBucket {
+                        year: *b.year(),
+                        month: *b.month(),
+                        day: *b.day(),
+                        count: *b.count() as i32,
+                    })
+                    .collect(),
+            })),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to get timeline"))
+            }
+        }
+    }
+
+    async fn memories(
+        &self,
+        request: Request<MemoriesRequest>,
+    ) -> std::result::Result<Response<MemoriesResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let utc_offset = utc_offset_minutes(req_info.utc_offset_minutes)?;
+        let per_year = req_info.size.map_or(DEFAULT_MEMORIES_SIZE, |s| {
+            (s as i64).clamp(1, MAX_PAGE_SIZE)
+        });
+        let offset = time::UtcOffset::from_whole_seconds(utc_offset * 60)
+            .map_err(|_| Status::invalid_argument("utcOffsetMinutes is out of range"))?;
+        let today = time::OffsetDateTime::now_utc().to_offset(offset).date();
+
+        let memories =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .memories(user_id, today, utc_offset, per_year)
+                .await;
+
+        match memories {
+            Ok(memories) => Ok(Response::new(MemoriesResponse {
+                memories: memories
+                    .iter()
+                    .map(|m| Memory {
+                        year: *m.year(),
+                        years_ago: today.year() - m.year(),
+                        images: m.photos().iter().map(GalleryImage::from).collect(),
+                    })
+                    .collect(),
+            })),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to get memories"))
+            }
+        }
+    }
+
+    async fn map_clusters(
+        &self,
+        request: Request<MapClustersRequest>,
+    ) -> std::result::Result<Response<MapClustersResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let bbox = bounding_box(req_info)?;
+        if !(0..=MAX_MAP_ZOOM).contains(&req_info.zoom) {
+            return Err(Status::invalid_argument(format!(
+                "zoom must be between 0 and {MAX_MAP_ZOOM}"
+            )));
+        }
+        let cell_degrees = 360.0 / (MAP_CELLS_PER_TILE * 2f64.powi(req_info.zoom));
+
+        let clusters =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .map_clusters(user_id, &bbox, cell_degrees)
+                .await;
+
+        match clusters {
+            Ok(clusters) => Ok(Response::new(MapClustersResponse {
+                clusters: clusters
+                    .iter()
+                    .map(|c| MapCluster {
+                        latitude: *c.latitude(),
+                        longitude: *c.longitude(),
+                        count: *c.count() as i32,
+                        photo_id: c.photo_id().to_string(),
+                        thumbnail_url: c.thumbnail_path().clone().unwrap_or_default(),
+                    })
+                    .collect(),
+            })),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to get map clusters"))
+            }
+        }
+    }
+
+    async fn stack_photos(
+        &self,
+        request: Request<StackRequest>,
+    ) -> std::result::Result<Response<GalleryImagesResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let stack_id = stack_id(&request.get_ref().id)?;
+
+        let photos =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .stack_photos(user_id, &stack_id)
+                .await;
+
+        match photos {
+            Ok(photos) if photos.is_empty() => Err(Status::not_found("Stack not found")),
+            Ok(photos) => {
+                let mut response: GalleryImagesResponse = photos.into();
+                response.count = response.images.len() as i32;
+                Ok(Response::new(response))
+            }
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to get stack photos"))
+            }
+        }
+    }
+
+    async fn set_stack_cover(
+        &self,
+        request: Request<StackCoverRequest>,
+    ) -> std::result::Result<Response<EmptyResponse>, Status> {
+        let user_id = match self.session_middleware.get_user(&request).await {
+            Ok(u) => u,
+            Err(x) => return Err(Status::unauthenticated(format!("{:?}", x))),
+        };
+        let req_info = request.get_ref();
+        let stack_id = stack_id(&req_info.id)?;
+        let gallery_id = Uuid::parse_str(&req_info.photo_id)
+            .map_err(|_| Status::invalid_argument("photoId is not a valid gallery id"))?;
+
+        let updated =
+            crate::gallery_view::model::UserGallery::new(self.conn.clone(), self.bucket.clone())
+                .set_stack_cover(user_id, &stack_id, &gallery_id)
+                .await;
+
+        match updated {
+            Ok(true) => Ok(Response::new(EmptyResponse {})),
+            Ok(false) => Err(Status::not_found("Photo not found in the stack")),
+            Err(e) => {
+                log::error!("{e:?}");
+                Err(Status::internal("Failed to set stack cover"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_defaults_empty_page_size() {
+        assert_eq!(page_size(&None).unwrap(), DEFAULT_PAGE_SIZE);
+        assert_eq!(page_size(&Some("".into())).unwrap(), DEFAULT_PAGE_SIZE);
+        assert_eq!(page_size(&Some(" ".into())).unwrap(), DEFAULT_PAGE_SIZE);
+        assert_eq!(page_size(&Some("10".into())).unwrap(), 10);
+        assert_eq!(page_size(&Some("100000".into())).unwrap(), MAX_PAGE_SIZE);
+        assert!(page_size(&Some("ten".into())).is_err());
     }
 }
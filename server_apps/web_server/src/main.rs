@@ -16,8 +16,10 @@ use crate::user_auth::UserSessions;
 
 mod bucket;
 mod config;
+mod embeddings;
 mod error;
 mod gallery_view;
+mod share_view;
 mod user_auth;
 
 #[get("/")]
@@ -56,7 +58,8 @@ async fn main() -> Result<()> {
     });
 
     let _ = tokio::join!(
-        rocket_task(shutdown_tx.subscribe()),
+        rocket_task(shutdown_tx.subscribe(), &config_s),
+        cleanup_task(shutdown_tx.subscribe(), &config_s),
         tonic_task(shutdown_rx, &config_s)
     );
 
@@ -64,11 +67,16 @@ async fn main() -> Result<()> {
 }
 
 /// Http server
-/// Tasks: healthcheck enpoints only
-async fn rocket_task(mut shutdown_rx: broadcast::Receiver<()>) {
+/// Tasks: healthcheck and anonymous share links.
+async fn rocket_task(mut shutdown_rx: broadcast::Receiver<()>, config: &'static config::Config) {
+    let db_pool = db_connect(&config.db().url()).await.unwrap();
+    let bucket_client = bucket::BucketClient::new(&config.bucket()).unwrap();
+
     // Http server
     let rocket_server = rocket::build()
+        .manage(share_view::ShareState::new(db_pool, bucket_client))
         .mount("/hearbeat", routes![get_heartbeat])
+        .mount("/share", routes![share_view::get_shared, share_view::head_shared])
         .launch();
 
     tokio::select! {
@@ -81,6 +89,29 @@ async fn rocket_task(mut shutdown_rx: broadcast::Receiver<()>) {
     }
 }
 
+/// Storage cleanup
+/// Tasks: retry the bucket deletes that failed during a photo delete.
+async fn cleanup_task(mut shutdown_rx: broadcast::Receiver<()>, config: &'static config::Config) {
+    let db_pool = db_connect(&config.db().url()).await.unwrap();
+    let bucket_client = bucket::BucketClient::new(&config.bucket()).unwrap();
+    let user_gallery = gallery_view::model::UserGallery::new(db_pool, bucket_client);
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(300));
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = user_gallery.retry_pending_deletes(100).await {
+                    log::error!("Cleanup task. {e:?}");
+                }
+            },
+            _ = shutdown_rx.recv() => {
+                log::warn!("Cleanup task. Stop signal comming from system");
+                break;
+            }
+        }
+    }
+}
+
 /// gRPC server
 /// Taks: Business tasks and app facing endpoints.
 async fn tonic_task(mut shutdown_rx: broadcast::Receiver<()>, config: &'static config::Config) {
@@ -95,8 +126,14 @@ async fn tonic_task(mut shutdown_rx: broadcast::Receiver<()>, config: &'static c
 
     let db_pool = db_connect(&config.db().url()).await.unwrap();
     let bucket_client = bucket::BucketClient::new(&config.bucket()).unwrap();
-    let img_gallery =
-        gallery_view::GalleryService::new(db_pool.clone(), bucket_client, session_middleware);
+    let text_embedder = embeddings::TextEmbedder::new().unwrap();
+    let img_gallery = gallery_view::GalleryService::new(
+        db_pool.clone(),
+        bucket_client,
+        session_middleware,
+        text_embedder,
+        config.share().public_url(),
+    );
 
     let grpc_server = Server::builder()
         .add_service(user_auth::AuthGreeterServer::new(greeter))
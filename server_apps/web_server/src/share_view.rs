@@ -0,0 +1,249 @@
+use db_storage::models::share_links::{ShareLink, SharedContent};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::content::RawHtml;
+use rocket::{Request, State};
+
+use crate::bucket::{Bucket, BucketClient};
+use crate::error::Result;
+
+/// User agent fragments of link previews and crawlers, lowercase.
+const BOT_AGENTS: [&str; 10] = [
+    "bot",
+    "crawler",
+    "spider",
+    "preview",
+    "facebookexternalhit",
+    "slack",
+    "discord",
+    "whatsapp",
+    "telegram",
+    "embedly",
+];
+
+/// Shared by the anonymous share routes.
+pub struct ShareState {
+    conn: db_storage::DbConn,
+    bucket: BucketClient<'static>,
+}
+
+impl ShareState {
+    pub fn new(conn: db_storage::DbConn, bucket: BucketClient<'static>) -> Self {
+        Self { conn, bucket }
+    }
+
+    /// Resolves the token and replaces the bucket paths with signed urls.
+    /// Crawlers only get the signed thumbnail of the first photo.
+    async fn shared_content(&self, token: &str, visitor: Visitor) -> Result<Option<SharedContent>> {
+        let count_view = visitor == Visitor::Person;
+        let mut content = match ShareLink::resolve(&self.conn, token, count_view).await? {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        if visitor == Visitor::Crawler {
+            content.photos_mut().truncate(1);
+            if let Some(photo) = content.photos_mut().first_mut()
+                && let Some(path) = photo.thumbnail_path()
+            {
+                let thumbnail = self
+                    .bucket
+                    .get_download_signed_url(path, Bucket::Ragged)
+                    .await?;
+                photo.set_signed_thumbnail(thumbnail);
+            }
+            return Ok(Some(content));
+        }
+
+        for photo in content.photos_mut().iter_mut() {
+            // Only processed photos are shared, the original lives in ragged.
+            let thumbnail = match photo.thumbnail_path() {
+                Some(path) => {
+                    self.bucket
+                        .get_download_signed_url(path, Bucket::Ragged)
+                        .await?
+                }
+                None => continue,
+            };
+            let original = self
+                .bucket
+                .get_download_signed_url(photo.path(), Bucket::Ragged)
+                .await?;
+            photo.set_signed_urls(original, thumbnail);
+        }
+
+        Ok(Some(content))
+    }
+}
+
+/// Who the page is rendered for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Visitor {
+    /// Counted against `max_views` and shown the photos.
+    Person,
+    /// Link previews and HEAD requests. Not counted, they only get the title,
+    /// the description and the first thumbnail, never the originals.
+    Crawler,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Visitor {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // Requests without a user agent are counted, they get the photos.
+        match request.headers().get_one("User-Agent") {
+            Some(agent) if is_bot(agent) => Outcome::Success(Visitor::Crawler),
+            _ => Outcome::Success(Visitor::Person),
+        }
+    }
+}
+
+fn is_bot(user_agent: &str) -> bool {
+    let user_agent = user_agent.to_lowercase();
+    BOT_AGENTS.iter().any(|bot| user_agent.contains(bot))
+}
+
+/// Share page. No session required, the token is the credential.
+#[get("/<token>")]
+pub async fn get_shared(
+    token: &str,
+    visitor: Visitor,
+    state: &State<ShareState>,
+) -> std::result::Result<RawHtml<String>, Status> {
+    shared_page(token, visitor, state).await
+}
+
+/// Preview page, not counted. Rocket would send HEAD requests to
+/// `get_shared` otherwise.
+#[head("/<token>")]
+pub async fn head_shared(
+    token: &str,
+    state: &State<ShareState>,
+) -> std::result::Result<RawHtml<String>, Status> {
+    shared_page(token, Visitor::Crawler, state).await
+}
+
+async fn shared_page(
+    token: &str,
+    visitor: Visitor,
+    state: &ShareState,
+) -> std::result::Result<RawHtml<String>, Status> {
+    match state.shared_content(token, visitor).await {
+        Ok(Some(content)) => Ok(RawHtml(render_page(&content, visitor))),
+        Ok(None) => Err(Status::NotFound),
+        Err(e) => {
+            log::error!("{e:?}");
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+fn render_page(content: &SharedContent, visitor: Visitor) -> String {
+    let first = content.photos().first();
+    let title = match (content.title(), first.and_then(|p| p.img_alt().as_ref())) {
+        (Some(title), _) => title.as_str(),
+        (None, Some(alt)) => alt.as_str(),
+        (None, None) => "Shared photo",
+    };
+    let description = first
+        .and_then(|p| p.description().as_ref())
+        .map_or("", |d| d.as_str());
+
+    let mut head = vec![
+        format!("<title>{}</title>", escape_html(title)),
+        r#"<meta name="robots" content="noindex">"#.to_string(),
+        r#"<meta property="og:type" content="website">"#.to_string(),
+        format!(
+            r#"<meta property="og:title" content="{}">"#,
+            escape_html(title)
+        ),
+        format!(
+            r#"<meta property="og:description" content="{}">"#,
+            escape_html(description)
+        ),
+    ];
+    if let Some(photo) = first {
+        let image = photo.thumbnail_path().as_ref().map_or("", |p| p.as_str());
+        head.push(format!(
+            r#"<meta property="og:image" content="{}">"#,
+            escape_html(image)
+        ));
+        if let Some(alt) = photo.img_alt() {
+            head.push(format!(
+                r#"<meta property="og:image:alt" content="{}">"#,
+                escape_html(alt)
+            ));
+        }
+    }
+
+    // The previews only need the meta tags.
+    let photos: &[_] = match visitor {
+        Visitor::Person => content.photos().as_slice(),
+        Visitor::Crawler => &[],
+    };
+    let figures: Vec<String> = photos
+        .iter()
+        .map(|photo| {
+            let thumbnail = photo.thumbnail_path().as_ref().map_or("", |p| p.as_str());
+            let alt = photo.img_alt().as_ref().map_or("", |a| a.as_str());
+            let caption = photo.description().as_ref().map_or("", |d| d.as_str());
+            format!(
+                r#"<figure><a href="{}"><img src="{}" alt="{}" loading="lazy"></a><figcaption>{}</figcaption></figure>"#,
+                escape_html(photo.path()),
+                escape_html(thumbnail),
+                escape_html(alt),
+                escape_html(caption)
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html><html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1">{}</head><body><h1>{}</h1>{}</body></html>"#,
+        head.join(""),
+        escape_html(title),
+        figures.join("")
+    )
+}
+
+/// Descriptors come from the LLM and the users, they are escaped before
+/// going into the page.
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_bot() {
+        assert!(is_bot(
+            "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)"
+        ));
+        assert!(is_bot("facebookexternalhit/1.1"));
+        assert!(is_bot("WhatsApp/2.23.20.0"));
+        assert!(!is_bot(
+            "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0"
+        ));
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+    }
+}